@@ -0,0 +1,832 @@
+use anyhow::{bail, Result};
+use clap::ValueEnum;
+use indexmap::IndexMap;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::bands::{BandsConfig, RebalanceTo};
+use crate::Config;
+
+/// A node of the `[asset_classes]` tree. A class either lists the funds that
+/// hold it or is split into subclasses. Targets are percentages of the whole
+/// portfolio, so a parent's target is the sum of its subclasses' targets.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AssetClass {
+    pub target: Option<Decimal>,
+    /// Interchangeable funds for the class, primary first
+    #[serde(default)]
+    pub funds: Vec<String>,
+    #[serde(flatten)]
+    pub classes: IndexMap<String, AssetClass>,
+}
+
+/// Which orders a rebalance may generate, selectable with `--mode`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+pub enum Mode {
+    /// Buy and sell to move every class to its target
+    #[default]
+    Full,
+    /// Only spend the free cash, on the most underweight classes
+    BuyOnly,
+    /// Only raise the missing cash, from the most overweight classes
+    SellOnly,
+}
+
+/// A class that is held directly by funds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Class {
+    /// Dotted path in the tree, such as `equity.us`
+    pub name: String,
+    pub target: Decimal,
+    pub funds: Vec<String>,
+}
+
+impl Class {
+    /// Whether this class is `name` or one of its subclasses.
+    pub fn is_in(&self, name: &str) -> bool {
+        self.name == name
+            || self
+                .name
+                .strip_prefix(name)
+                .is_some_and(|rest| rest.starts_with('.'))
+    }
+}
+
+/// What to do with holdings of funds that have no target.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Unmanaged {
+    /// Sell the whole position and rebalance the proceeds
+    Liquidate,
+    /// Leave the position alone and out of the portfolio total
+    #[default]
+    Ignore,
+    /// Refuse to generate orders
+    Error,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct TargetsConfig {
+    /// Largest difference, in percentage points, between the sum of the
+    /// targets and 100 that is still accepted, to allow for rounding
+    pub tolerance: Decimal,
+    pub unmanaged_holdings: Unmanaged,
+}
+
+impl Default for TargetsConfig {
+    fn default() -> Self {
+        TargetsConfig {
+            tolerance: Decimal::new(1, 2),
+            unmanaged_holdings: Unmanaged::default(),
+        }
+    }
+}
+
+/// Cash held back from the allocation, as a fixed amount in the base
+/// currency or as a percentage of the portfolio. Targets apply to what is
+/// left after the reserve.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct CashReserve {
+    pub amount: Option<Decimal>,
+    pub percent: Option<Decimal>,
+}
+
+impl CashReserve {
+    /// The cash to hold back from a portfolio worth `total_value`.
+    pub fn of(&self, total_value: Decimal) -> Decimal {
+        let reserve = match (self.amount, self.percent) {
+            (Some(amount), _) => amount,
+            (None, Some(percent)) => total_value * (percent / Decimal::from(100)),
+            (None, None) => Decimal::ZERO,
+        };
+        reserve.min(total_value).max(Decimal::ZERO)
+    }
+}
+
+/// Name of the target for money deliberately held as cash, in `[funds]` or
+/// as a top-level asset class without funds.
+const CASH: &str = "cash";
+
+/// Checks the targets in the config and returns the classes to rebalance,
+/// sorted by name. Each `[funds]` entry is a class of its own, held by that
+/// one fund. Every problem found is reported, each with its TOML key path.
+pub fn classes(config: &Config) -> Result<Vec<Class>> {
+    if config.funds.is_empty() && config.asset_classes.is_empty() {
+        bail!("Config has no [funds] or [asset_classes] targets");
+    }
+
+    let mut validator = Validator {
+        tolerance: config.targets.tolerance,
+        errors: Vec::new(),
+        symbols: HashMap::new(),
+        classes: Vec::new(),
+    };
+    if !config.funds.is_empty() && !config.asset_classes.is_empty() {
+        validator.error("asset_classes", "cannot be used together with [funds]");
+    }
+
+    let mut total = Decimal::ZERO;
+    for (symbol, target) in sorted(&config.funds) {
+        let key = format!("funds.{}", symbol);
+        validator.target(&key, *target);
+        total += target;
+        if symbol.eq_ignore_ascii_case(CASH) {
+            continue;
+        }
+        validator.fund(&key, symbol);
+        validator.classes.push(Class {
+            name: symbol.clone(),
+            target: *target,
+            funds: vec![symbol.clone()],
+        });
+    }
+    for (name, class) in sorted(&config.asset_classes) {
+        let key = format!("asset_classes.{}", name);
+        if name.eq_ignore_ascii_case(CASH) {
+            total += validator.cash(&key, class);
+        } else {
+            total += validator.class(&key, name, class);
+        }
+    }
+
+    let hundred = Decimal::from(100);
+    let reserve = &config.cash_reserve;
+    if reserve.amount.is_some() && reserve.percent.is_some() {
+        validator.error("cash_reserve", "set either amount or percent, not both");
+    }
+    if let Some(amount) = reserve.amount {
+        validator.target("cash_reserve.amount", amount);
+    }
+    if let Some(percent) = reserve.percent {
+        validator.target("cash_reserve.percent", percent);
+        if percent > hundred {
+            validator.error(
+                "cash_reserve.percent",
+                format!("{}% is more than the whole portfolio", percent.normalize()),
+            );
+        }
+    }
+
+    if (total - hundred).abs() > validator.tolerance {
+        let key = if config.funds.is_empty() {
+            "asset_classes"
+        } else {
+            "funds"
+        };
+        validator.error(
+            key,
+            format!(
+                "targets add up to {}%, not 100% (tolerance {} points)",
+                total.normalize(),
+                validator.tolerance.normalize()
+            ),
+        );
+    }
+
+    let classes = &validator.classes;
+    let band_errors = config.bands.errors(classes);
+    let unknown_shares: Vec<String> = sorted(&config.shares)
+        .into_iter()
+        .filter(|(symbol, _)| !classes.iter().any(|class| class.funds.contains(symbol)))
+        .map(|(symbol, _)| format!("shares.{}: not a fund listed with a target", symbol))
+        .collect();
+    let unknown_settlement: Vec<String> = sorted(&config.settlement.funds)
+        .into_iter()
+        .filter(|(symbol, _)| !classes.iter().any(|class| class.funds.contains(symbol)))
+        .map(|(symbol, _)| {
+            format!(
+                "settlement.funds.{}: not a fund listed with a target",
+                symbol
+            )
+        })
+        .collect();
+    let location_errors: Vec<String> = config
+        .asset_location
+        .iter()
+        .enumerate()
+        .flat_map(|(index, rule)| rule.errors(index, classes))
+        .collect();
+    validator.errors.extend(band_errors);
+    validator.errors.extend(location_errors);
+    validator.errors.extend(unknown_shares);
+    validator.errors.extend(unknown_settlement);
+
+    if !validator.errors.is_empty() {
+        bail!("Invalid config:\n  {}", validator.errors.join("\n  "));
+    }
+    let mut classes = validator.classes;
+    classes.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(classes)
+}
+
+/// Funds in the order the config lists them: `[funds]` in table order, or
+/// each class's funds before those of its subclasses.
+pub fn fund_order(config: &Config) -> Vec<&str> {
+    fn walk<'a>(classes: &'a IndexMap<String, AssetClass>, funds: &mut Vec<&'a str>) {
+        for class in classes.values() {
+            funds.extend(class.funds.iter().map(String::as_str));
+            walk(&class.classes, funds);
+        }
+    }
+
+    let mut funds: Vec<&str> = config
+        .funds
+        .keys()
+        .map(String::as_str)
+        .filter(|fund| !fund.eq_ignore_ascii_case(CASH))
+        .collect();
+    walk(&config.asset_classes, &mut funds);
+    funds
+}
+
+/// Entries of a TOML table in key order, so errors are reported the same
+/// way every run.
+fn sorted<'a, V: 'a>(
+    table: impl IntoIterator<Item = (&'a String, &'a V)>,
+) -> Vec<(&'a String, &'a V)> {
+    let mut entries: Vec<_> = table.into_iter().collect();
+    entries.sort_by_key(|(key, _)| *key);
+    entries
+}
+
+/// Whether `symbol` looks like a ticker or fund code.
+fn is_symbol(symbol: &str) -> bool {
+    !symbol.is_empty()
+        && symbol
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '-' | '_' | ':' | '/'))
+}
+
+/// Whether `name` can be an account id or type: letters, digits, `_` or `-`.
+pub fn is_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+/// Collects the classes and the problems found while walking the targets.
+struct Validator {
+    tolerance: Decimal,
+    errors: Vec<String>,
+    /// Key path of each fund seen so far, by uppercased symbol
+    symbols: HashMap<String, String>,
+    classes: Vec<Class>,
+}
+
+impl Validator {
+    fn error(&mut self, key: &str, message: impl fmt::Display) {
+        self.errors.push(format!("{}: {}", key, message));
+    }
+
+    fn target(&mut self, key: &str, target: Decimal) {
+        if target < Decimal::ZERO {
+            self.error(key, format!("{} is negative", target.normalize()));
+        }
+    }
+
+    fn fund(&mut self, key: &str, symbol: &str) {
+        if !is_symbol(symbol) {
+            self.error(
+                key,
+                format!(
+                    "'{}' is not a fund symbol (use letters, digits, '.', '-', '_', ':' or '/')",
+                    symbol
+                ),
+            );
+        } else if let Some(other) = self.symbols.get(&symbol.to_uppercase()) {
+            let message = format!("{} is already listed at {}", symbol, other);
+            self.error(key, message);
+        } else {
+            self.symbols.insert(symbol.to_uppercase(), key.to_string());
+        }
+    }
+
+    /// Checks the explicit cash target and returns it.
+    fn cash(&mut self, key: &str, class: &AssetClass) -> Decimal {
+        if !class.funds.is_empty() || !class.classes.is_empty() {
+            self.error(key, "the cash target cannot list funds or subclasses");
+        }
+        let Some(target) = class.target else {
+            self.error(&format!("{}.target", key), "missing target");
+            return Decimal::ZERO;
+        };
+        self.target(&format!("{}.target", key), target);
+        target
+    }
+
+    /// Adds the classes held by funds under `class`, and returns the target
+    /// of `class`.
+    fn class(&mut self, key: &str, name: &str, class: &AssetClass) -> Decimal {
+        let target_key = format!("{}.target", key);
+        if let Some(target) = class.target {
+            self.target(&target_key, target);
+        }
+
+        if class.classes.is_empty() {
+            let Some(target) = class.target else {
+                self.error(&target_key, "missing target");
+                return Decimal::ZERO;
+            };
+            if class.funds.is_empty() {
+                self.error(&format!("{}.funds", key), "no funds listed");
+            }
+            for (index, fund) in class.funds.iter().enumerate() {
+                self.fund(&format!("{}.funds[{}]", key, index), fund);
+            }
+            self.classes.push(Class {
+                name: name.to_string(),
+                target,
+                funds: class.funds.clone(),
+            });
+            return target;
+        }
+
+        if !class.funds.is_empty() {
+            self.error(
+                &format!("{}.funds", key),
+                "a class with subclasses cannot list funds",
+            );
+        }
+        let mut total = Decimal::ZERO;
+        for (child, subclass) in sorted(&class.classes) {
+            total += self.class(
+                &format!("{}.{}", key, child),
+                &format!("{}.{}", name, child),
+                subclass,
+            );
+        }
+        if let Some(target) = class.target {
+            if (target - total).abs() > self.tolerance {
+                self.error(
+                    &target_key,
+                    format!(
+                        "target is {}% but its subclasses add up to {}%",
+                        target.normalize(),
+                        total.normalize()
+                    ),
+                );
+            }
+        }
+        total
+    }
+}
+
+/// Splits each class's target value among its funds, given the household
+/// value `held` of each fund and the `cash` free to spend (negative if cash
+/// has to be raised), which includes the net contribution `flow`.
+///
+/// In the full mode, a contribution is first spent on the most underweight
+/// classes and a withdrawal raised from the most overweight ones, as in
+/// [`water_fill`]. Then a class within its band keeps its value. One
+/// outside it is moved to its target or the edge of its band, and if the
+/// classes to buy need more cash than is free plus what the others sell,
+/// their buys are scaled down to fit. In the buy-only and sell-only modes,
+/// only the free or missing cash is traded, as in [`water_fill`].
+///
+/// A class below target then buys its primary (first-listed) fund; a class
+/// above target sells its funds last-listed first, so secondary funds are
+/// wound down before the primary one.
+pub fn fund_targets(
+    classes: &[Class],
+    total_value: Decimal,
+    cash: Decimal,
+    flow: Decimal,
+    bands: &BandsConfig,
+    mode: Mode,
+    held: impl Fn(&str) -> Decimal,
+) -> HashMap<String, Decimal> {
+    let hundred = Decimal::from(100);
+    let current: Vec<Decimal> = classes
+        .iter()
+        .map(|class| class.funds.iter().map(|fund| held(fund)).sum())
+        .collect();
+    let target_values: Vec<Decimal> = classes
+        .iter()
+        .map(|class| total_value * (class.target / hundred))
+        .collect();
+
+    let buy = |current: &[Decimal], cash: Decimal| -> Vec<Decimal> {
+        let gaps: Vec<Decimal> = target_values
+            .iter()
+            .zip(current)
+            .map(|(&target, &current)| target - current)
+            .collect();
+        let caps: Vec<Decimal> = gaps.iter().map(|&gap| gap.max(Decimal::ZERO)).collect();
+        let buys = water_fill(&gaps, &caps, cash);
+        current.iter().zip(buys).map(|(&c, buy)| c + buy).collect()
+    };
+    let sell = |current: &[Decimal], cash: Decimal| -> Vec<Decimal> {
+        let gaps: Vec<Decimal> = target_values
+            .iter()
+            .zip(current)
+            .map(|(&target, &current)| current - target)
+            .collect();
+        let sells = water_fill(&gaps, current, cash);
+        current
+            .iter()
+            .zip(sells)
+            .map(|(&c, sell)| c - sell)
+            .collect()
+    };
+
+    let class_targets: Vec<Decimal> = match mode {
+        Mode::Full => {
+            let current = if flow > Decimal::ZERO {
+                buy(&current, flow.min(cash))
+            } else if flow < Decimal::ZERO {
+                sell(&current, -cash)
+            } else {
+                current
+            };
+            banded_targets(classes, total_value, bands, &current, &target_values)
+        }
+        Mode::BuyOnly => buy(&current, cash),
+        Mode::SellOnly => sell(&current, -cash),
+    };
+
+    let mut targets = HashMap::new();
+    for (class, class_target) in classes.iter().zip(class_targets) {
+        let mut values: Vec<Decimal> = class.funds.iter().map(|fund| held(fund)).collect();
+        let mut difference = class_target - values.iter().sum::<Decimal>();
+
+        if difference >= Decimal::ZERO {
+            values[0] += difference;
+        } else {
+            for value in values.iter_mut().rev() {
+                let sold = (-difference).min(*value);
+                *value -= sold;
+                difference += sold;
+            }
+        }
+        targets.extend(class.funds.iter().cloned().zip(values));
+    }
+    targets
+}
+
+/// Class targets for the full mode: classes outside their band are moved
+/// back into it, with buys scaled down to the cash available.
+fn banded_targets(
+    classes: &[Class],
+    total_value: Decimal,
+    bands: &BandsConfig,
+    current: &[Decimal],
+    target_values: &[Decimal],
+) -> Vec<Decimal> {
+    let mut class_targets: Vec<Decimal> = classes
+        .iter()
+        .zip(current.iter().zip(target_values))
+        .map(|(class, (&current, &target_value))| {
+            let band = bands.band(class);
+            let width = total_value * (band.width / Decimal::from(100));
+            let drift = current - target_value;
+            if drift.abs() <= width {
+                return current;
+            }
+            match band.rebalance_to {
+                RebalanceTo::Target => target_value,
+                RebalanceTo::Edge if drift > Decimal::ZERO => target_value + width,
+                RebalanceTo::Edge => target_value - width,
+            }
+        })
+        .collect();
+
+    let (mut buys, mut sells) = (Decimal::ZERO, Decimal::ZERO);
+    for (&target, &current) in class_targets.iter().zip(current) {
+        if target > current {
+            buys += target - current;
+        } else {
+            sells += current - target;
+        }
+    }
+    let cash = total_value - current.iter().sum::<Decimal>() + sells;
+    if buys > cash {
+        let scale = cash.max(Decimal::ZERO) / buys;
+        for (target, &current) in class_targets.iter_mut().zip(current) {
+            if *target > current {
+                *target = current + (*target - current) * scale;
+            }
+        }
+    }
+    class_targets
+}
+
+/// Splits `amount` among classes that are `gaps` away from their targets,
+/// largest gap first, so that the remaining gaps end up as even as possible.
+/// Each class gets `gap - level`, between zero and its cap, for the level at
+/// which the shares add up to `amount` (or to all the caps, if less).
+fn water_fill(gaps: &[Decimal], caps: &[Decimal], amount: Decimal) -> Vec<Decimal> {
+    let fill = |level: Decimal| -> Vec<Decimal> {
+        gaps.iter()
+            .zip(caps)
+            .map(|(&gap, &cap)| (gap - level).max(Decimal::ZERO).min(cap))
+            .collect()
+    };
+    let filled = |level: Decimal| fill(level).iter().sum::<Decimal>();
+    if amount <= Decimal::ZERO || gaps.is_empty() {
+        return vec![Decimal::ZERO; gaps.len()];
+    }
+
+    // The filled amount only changes slope where a class starts or stops
+    // taking a share, so find the pair of those levels that `amount` falls
+    // between and interpolate
+    let mut levels: Vec<Decimal> = gaps
+        .iter()
+        .zip(caps)
+        .flat_map(|(&gap, &cap)| [gap, gap - cap])
+        .collect();
+    levels.sort();
+    levels.dedup();
+    for pair in levels.windows(2).rev() {
+        let (low, high) = (pair[0], pair[1]);
+        let (at_low, at_high) = (filled(low), filled(high));
+        if at_low >= amount {
+            return fill(high - (amount - at_high) * (high - low) / (at_low - at_high));
+        }
+    }
+    fill(levels[0])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"
+[asset_classes.equity]
+target = 60
+
+[asset_classes.equity.us]
+target = 40
+funds = ["VTSAX", "VTI"]
+
+[asset_classes.equity.international]
+target = 20
+funds = ["VTIAX"]
+
+[asset_classes.fixed_income]
+target = "40.0"
+funds = ["VBTLX"]
+"#;
+
+    fn class(name: &str, target: i64, funds: &[&str]) -> Class {
+        Class {
+            name: name.to_string(),
+            target: Decimal::from(target),
+            funds: funds.iter().map(|f| f.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn test_classes() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        let leaves = classes(&config).unwrap();
+        assert_eq!(
+            leaves,
+            vec![
+                class("equity.international", 20, &["VTIAX"]),
+                class("equity.us", 40, &["VTSAX", "VTI"]),
+                class("fixed_income", 40, &["VBTLX"]),
+            ]
+        );
+        assert!(leaves[0].is_in("equity"));
+        assert!(!leaves[2].is_in("fixed"));
+
+        // Flat [funds] targets are classes of one fund each
+        let config: Config = toml::from_str("[funds]\nABC123 = 100\n").unwrap();
+        assert_eq!(
+            classes(&config).unwrap(),
+            vec![class("ABC123", 100, &["ABC123"])]
+        );
+    }
+
+    #[test]
+    fn test_fund_order() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+        assert_eq!(fund_order(&config), vec!["VTSAX", "VTI", "VTIAX", "VBTLX"]);
+
+        let config: Config = toml::from_str("[funds]\nZZZ = 50\ncash = 10\nAAA = 40\n").unwrap();
+        assert_eq!(fund_order(&config), vec!["ZZZ", "AAA"]);
+    }
+
+    #[test]
+    fn test_classes_errors() {
+        let error = |toml: &str| {
+            let config: Config = toml::from_str(toml).unwrap();
+            classes(&config).unwrap_err().to_string()
+        };
+
+        let message = error(&CONFIG.replace("target = 60", "target = 65"));
+        assert!(message.contains(
+            "asset_classes.equity.target: target is 65% but its subclasses add up to 60%"
+        ));
+        assert!(error(&format!("{}\n[funds]\nVTSAX = 100\n", CONFIG))
+            .contains("asset_classes: cannot be used together with [funds]"));
+        assert!(error("[asset_classes.bonds]\ntarget = 100\n")
+            .contains("asset_classes.bonds.funds: no funds listed"));
+        assert!(error("[asset_classes.bonds]\nfunds = [\"VBTLX\"]\n")
+            .contains("asset_classes.bonds.target: missing target"));
+        assert!(error(&CONFIG.replace("[\"VTIAX\"]", "[\"VTI\"]")).contains(
+            "asset_classes.equity.us.funds[1]: VTI is already listed at asset_classes.equity.international.funds[0]"
+        ));
+        assert!(error("").contains("no [funds] or [asset_classes]"));
+        assert!(error(&format!(
+            "{}\n[shares]\nVTI = \"whole\"\nXEQT = \"whole\"\n",
+            CONFIG
+        ))
+        .contains("shares.XEQT: not a fund listed with a target"));
+        assert!(error(&format!(
+            "{}\n[settlement.funds]\nVTI = 1\nVTSX = 1\n",
+            CONFIG
+        ))
+        .contains("settlement.funds.VTSX: not a fund listed with a target"));
+
+        let message = error(&format!(
+            "{}\n[[asset_location]]\nclass = \"fixed_income\"\naccounts = [\"rrsp\"]\n\
+             [[asset_location]]\nclass = \"equity.intl\"\naccounts = [\"tfsa\"]\n\
+             [[asset_location]]\nclass = \"equity\"\nfunds = [\"VTI\", \"VBTLX\"]\n\
+             accounts = [\"rrsp\", \"non registered\"]\n\
+             [[asset_location]]\nclass = \"fixed_income\"\naccounts = []\n\
+             [[asset_location]]\nclass = \"bonds\"\nfunds = [\"VBTLX\", \"BND\"]\naccounts = [\"rrsp\"]\n",
+            CONFIG
+        ));
+        assert!(!message.contains("asset_location[0]"));
+        assert!(message.contains(
+            "asset_location[1].class: 'equity.intl' is not a fund or asset class with a target"
+        ));
+        assert!(!message.contains("asset_location[4].class"));
+        assert!(!message.contains("asset_location[4].funds[0]"));
+        assert!(
+            message.contains("asset_location[4].funds[1]: BND is not a fund listed with a target")
+        );
+        assert!(message.contains("asset_location[2].funds[1]: VBTLX is not a fund of equity"));
+        assert!(!message.contains("asset_location[2].funds[0]"));
+        assert!(message
+            .contains("asset_location[2].accounts[1]: 'non registered' is not an account type"));
+        assert!(message.contains("asset_location[3].accounts: no account types listed"));
+    }
+
+    #[test]
+    fn test_validate_targets() {
+        let error = |toml: &str| {
+            let config: Config = toml::from_str(toml).unwrap();
+            classes(&config).unwrap_err().to_string()
+        };
+        let valid = |toml: &str| {
+            let config: Config = toml::from_str(toml).unwrap();
+            classes(&config).unwrap()
+        };
+
+        assert!(error("[funds]\nABC123 = 60\nABC456 = 30\n")
+            .contains("funds: targets add up to 90%, not 100% (tolerance 0.01 points)"));
+        assert!(error("[funds]\nABC123 = 60\nABC456 = 50\n").contains("add up to 110%"));
+
+        // Every problem is listed, in key order
+        let message = error("[funds]\nABC123 = 110\nabc123 = 0\n\"BAD FUND\" = \"-10\"\n");
+        assert_eq!(
+            message,
+            "Invalid config:\n  \
+             funds.BAD FUND: -10 is negative\n  \
+             funds.BAD FUND: 'BAD FUND' is not a fund symbol (use letters, digits, '.', '-', '_', ':' or '/')\n  \
+             funds.abc123: abc123 is already listed at funds.ABC123"
+        );
+
+        // Rounding within the tolerance is accepted
+        let thirds = "[funds]\nABC123 = 33.33\nABC456 = 33.33\nABC789 = 33.33\n";
+        assert_eq!(valid(thirds).len(), 3);
+        assert!(error(&format!("{}[targets]\ntolerance = 0\n", thirds))
+            .contains("targets add up to 99.99%, not 100% (tolerance 0 points)"));
+
+        // An explicit cash target is not a fund
+        assert_eq!(
+            valid("[funds]\nABC123 = 95\ncash = 5\n"),
+            vec![class("ABC123", 95, &["ABC123"])]
+        );
+        let leaves = valid("[asset_classes.cash]\ntarget = 5\n[asset_classes.equity]\ntarget = 95\nfunds = [\"VTSAX\"]\n");
+        assert_eq!(leaves, vec![class("equity", 95, &["VTSAX"])]);
+        assert!(
+            error("[asset_classes.cash]\ntarget = 100\nfunds = [\"MMF\"]\n")
+                .contains("asset_classes.cash: the cash target cannot list funds or subclasses")
+        );
+    }
+
+    #[test]
+    fn test_cash_reserve() {
+        let total = Decimal::from(10000);
+        let reserve = |toml: &str| {
+            let config: Config =
+                toml::from_str(&format!("[funds]\nABC123 = 100\n{}", toml)).unwrap();
+            classes(&config).map(|_| config.cash_reserve.of(total))
+        };
+
+        assert_eq!(reserve("").unwrap(), Decimal::ZERO);
+        assert_eq!(
+            reserve("[cash_reserve]\namount = 500\n").unwrap(),
+            Decimal::from(500)
+        );
+        assert_eq!(
+            reserve("[cash_reserve]\npercent = 2.5\n").unwrap(),
+            Decimal::from(250)
+        );
+        // The reserve never exceeds the portfolio
+        assert_eq!(reserve("[cash_reserve]\namount = 20000\n").unwrap(), total);
+
+        let error = |toml: &str| reserve(toml).unwrap_err().to_string();
+        assert!(error("[cash_reserve]\namount = 500\npercent = 5\n")
+            .contains("cash_reserve: set either amount or percent, not both"));
+        assert!(
+            error("[cash_reserve]\namount = -5\n").contains("cash_reserve.amount: -5 is negative")
+        );
+        assert!(error("[cash_reserve]\npercent = 150\n")
+            .contains("cash_reserve.percent: 150% is more than the whole portfolio"));
+    }
+
+    #[test]
+    fn test_fund_targets() {
+        let classes = vec![
+            class("equity", 60, &["VTSAX", "VTI"]),
+            class("bonds", 40, &["VBTLX", "BND"]),
+        ];
+        let held = HashMap::from([
+            ("VTSAX", Decimal::from(3000)),
+            ("VTI", Decimal::from(1000)),
+            ("VBTLX", Decimal::from(3000)),
+            ("BND", Decimal::from(2000)),
+        ]);
+
+        let targets = |mode: Mode| {
+            fund_targets(
+                &classes,
+                Decimal::from(10000),
+                Decimal::from(1000),
+                Decimal::ZERO,
+                &BandsConfig::default(),
+                mode,
+                |fund| held.get(fund).copied().unwrap_or_default(),
+            )
+        };
+
+        // Equity is 2000 under target: the primary fund buys it all.
+        // Bonds are 1000 over target: the secondary fund is sold first.
+        let full = targets(Mode::Full);
+        assert_eq!(full["VTSAX"], Decimal::from(5000));
+        assert_eq!(full["VTI"], Decimal::from(1000));
+        assert_eq!(full["VBTLX"], Decimal::from(3000));
+        assert_eq!(full["BND"], Decimal::from(1000));
+
+        // Without sells, only the 1000 of cash goes to equity
+        let buy_only = targets(Mode::BuyOnly);
+        assert_eq!(buy_only["VTSAX"], Decimal::from(4000));
+        assert_eq!(buy_only["BND"], Decimal::from(2000));
+
+        // No cash is missing, so nothing is sold
+        let sell_only = targets(Mode::SellOnly);
+        assert_eq!(sell_only["VTSAX"], Decimal::from(3000));
+        assert_eq!(sell_only["BND"], Decimal::from(2000));
+    }
+
+    #[test]
+    fn test_water_fill() {
+        let amounts =
+            |values: &[i64]| -> Vec<Decimal> { values.iter().map(|&v| Decimal::from(v)).collect() };
+
+        // Buying: the largest gap is filled until it meets the next one, then
+        // both are filled together, never past the target
+        let gaps = amounts(&[3000, 1000, -4000]);
+        let caps = amounts(&[3000, 1000, 0]);
+        assert_eq!(
+            water_fill(&gaps, &caps, Decimal::from(2400)),
+            amounts(&[2200, 200, 0])
+        );
+        assert_eq!(
+            water_fill(&gaps, &caps, Decimal::from(9000)),
+            amounts(&[3000, 1000, 0])
+        );
+        assert_eq!(
+            water_fill(&gaps, &caps, Decimal::from(-5)),
+            amounts(&[0, 0, 0])
+        );
+
+        // Selling: past the target if needed, but never more than is held
+        let gaps = amounts(&[-3000, -1000, 4000]);
+        let caps = amounts(&[2000, 3000, 10000]);
+        assert_eq!(
+            water_fill(&gaps, &caps, Decimal::from(5000)),
+            amounts(&[0, 0, 5000])
+        );
+        assert_eq!(
+            water_fill(&gaps, &caps, Decimal::from(6000)),
+            amounts(&[0, 500, 5500])
+        );
+        assert_eq!(
+            water_fill(&gaps, &caps, Decimal::from(20000)),
+            amounts(&[2000, 3000, 10000])
+        );
+
+        // Without any classes, as with only a cash target, nothing is filled
+        assert!(water_fill(&[], &[], Decimal::from(500)).is_empty());
+    }
+}
@@ -0,0 +1,229 @@
+use rust_decimal::Decimal;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer};
+use std::collections::HashMap;
+
+use crate::allocation::Class;
+
+/// How far a class that breached its band is traded.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RebalanceTo {
+    /// All the way back to the target
+    #[default]
+    Target,
+    /// Only back to the nearest edge of the band
+    Edge,
+}
+
+/// Band settings, either for every class or for one class or fund. Unset
+/// settings of an override fall back to the global ones.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BandConfig {
+    /// Allowed drift in percentage points of the portfolio
+    pub absolute: Option<Decimal>,
+    /// Allowed drift as a percentage of the target
+    pub relative: Option<Decimal>,
+    /// Orders of this amount or less are skipped
+    pub min_trade: Option<Decimal>,
+    pub rebalance_to: Option<RebalanceTo>,
+}
+
+/// The `[bands]` table: global settings, plus overrides keyed by a `[funds]`
+/// symbol or an asset class path.
+#[derive(Debug, Clone, Default)]
+pub struct BandsConfig {
+    pub global: BandConfig,
+    pub overrides: HashMap<String, BandConfig>,
+}
+
+impl<'de> Deserialize<'de> for BandsConfig {
+    /// Tables are overrides and everything else is a global setting, so that
+    /// unknown settings in either are reported with their key path.
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let mut global = toml::Table::new();
+        let mut overrides = HashMap::new();
+        for (key, value) in toml::Table::deserialize(deserializer)? {
+            match value {
+                toml::Value::Table(table) => {
+                    let band =
+                        toml::Value::Table(table)
+                            .try_into()
+                            .map_err(|e: toml::de::Error| {
+                                D::Error::custom(format!("bands.{}: {}", key, e.message()))
+                            })?;
+                    overrides.insert(key, band);
+                }
+                value => {
+                    global.insert(key, value);
+                }
+            }
+        }
+        let global = toml::Value::Table(global)
+            .try_into()
+            .map_err(|e: toml::de::Error| D::Error::custom(format!("bands: {}", e.message())))?;
+        Ok(BandsConfig { global, overrides })
+    }
+}
+
+/// The band a class is rebalanced within.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Band {
+    /// Allowed drift either side of the target, in percentage points
+    pub width: Decimal,
+    pub min_trade: Decimal,
+    pub rebalance_to: RebalanceTo,
+}
+
+impl BandsConfig {
+    /// The band for `class`. The most specific override applies: one for the
+    /// class itself over one for a parent class.
+    pub fn band(&self, class: &Class) -> Band {
+        let mut keys: Vec<&String> = self
+            .overrides
+            .keys()
+            .filter(|key| class.is_in(key))
+            .collect();
+        keys.sort_by_key(|key| std::cmp::Reverse(key.len()));
+        let setting = |get: fn(&BandConfig) -> Option<Decimal>| {
+            keys.iter()
+                .find_map(|key| get(&self.overrides[*key]))
+                .or(get(&self.global))
+        };
+
+        // With both limits set, the tighter one applies (the 5/25 rule)
+        let absolute = setting(|b| b.absolute);
+        let relative = setting(|b| b.relative).map(|r| class.target * r / Decimal::from(100));
+        let width = match (absolute, relative) {
+            (Some(absolute), Some(relative)) => absolute.min(relative),
+            (absolute, relative) => absolute.or(relative).unwrap_or_default(),
+        };
+        Band {
+            width,
+            min_trade: setting(|b| b.min_trade).unwrap_or(Decimal::ONE),
+            rebalance_to: keys
+                .iter()
+                .find_map(|key| self.overrides[*key].rebalance_to)
+                .or(self.global.rebalance_to)
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Problems with the band settings, each with its TOML key path.
+    pub fn errors(&self, classes: &[Class]) -> Vec<String> {
+        let mut errors = Vec::new();
+        check_band("bands", &self.global, &mut errors);
+        let mut keys: Vec<&String> = self.overrides.keys().collect();
+        keys.sort();
+        for key in keys {
+            check_band(&format!("bands.{}", key), &self.overrides[key], &mut errors);
+            if !classes.iter().any(|class| class.is_in(key)) {
+                errors.push(format!(
+                    "bands.{}: not a fund or asset class with a target",
+                    key
+                ));
+            }
+        }
+        errors
+    }
+}
+
+fn check_band(key: &str, band: &BandConfig, errors: &mut Vec<String>) {
+    for (name, value) in [
+        ("absolute", band.absolute),
+        ("relative", band.relative),
+        ("min_trade", band.min_trade),
+    ] {
+        if let Some(value) = value.filter(|v| *v < Decimal::ZERO) {
+            errors.push(format!(
+                "{}.{}: {} is negative",
+                key,
+                name,
+                value.normalize()
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn class(name: &str, target: i64) -> Class {
+        Class {
+            name: name.to_string(),
+            target: Decimal::from(target),
+            funds: vec![name.to_string()],
+        }
+    }
+
+    #[test]
+    fn test_band() {
+        let bands: BandsConfig = toml::from_str(
+            r#"
+absolute = 5
+relative = 25
+min_trade = 100
+
+[equity]
+rebalance_to = "edge"
+
+["equity.international"]
+absolute = 2
+"#,
+        )
+        .unwrap();
+
+        // 5/25 rule: 5 points for large targets, 25% of the target for small ones
+        let bonds = bands.band(&class("bonds", 40));
+        assert_eq!(bonds.width, Decimal::from(5));
+        assert_eq!(bonds.min_trade, Decimal::from(100));
+        assert_eq!(bonds.rebalance_to, RebalanceTo::Target);
+        assert_eq!(bands.band(&class("gold", 8)).width, Decimal::from(2));
+
+        // Overrides apply to subclasses, the most specific first
+        let us = bands.band(&class("equity.us", 40));
+        assert_eq!(us.width, Decimal::from(5));
+        assert_eq!(us.rebalance_to, RebalanceTo::Edge);
+        let international = bands.band(&class("equity.international", 20));
+        assert_eq!(international.width, Decimal::from(2));
+        assert_eq!(international.rebalance_to, RebalanceTo::Edge);
+
+        // Without bands, any drift is traded, down to $1
+        let none = BandsConfig::default().band(&class("bonds", 40));
+        assert_eq!(
+            none,
+            Band {
+                width: Decimal::ZERO,
+                min_trade: Decimal::ONE,
+                rebalance_to: RebalanceTo::Target,
+            }
+        );
+    }
+
+    #[test]
+    fn test_band_unknown_settings() {
+        let error = |toml: &str| toml::from_str::<BandsConfig>(toml).unwrap_err().to_string();
+        assert!(error("relatve = 25\n").contains("bands: unknown field `relatve`"));
+        assert!(error("absolute = 5\n[VBTLX]\nmin_trades = 100\n")
+            .contains("bands.VBTLX: unknown field `min_trades`"));
+        assert!(error("[VBTLX]\nrebalance_to = \"middle\"\n")
+            .contains("bands.VBTLX: unknown variant `middle`"));
+    }
+
+    #[test]
+    fn test_band_errors() {
+        let bands: BandsConfig =
+            toml::from_str("absolute = -1\n[VTSAX]\nmin_trade = -5\n[XYZ]\nrelative = 10\n")
+                .unwrap();
+        assert_eq!(
+            bands.errors(&[class("VTSAX", 100)]),
+            vec![
+                "bands.absolute: -1 is negative",
+                "bands.VTSAX.min_trade: -5 is negative",
+                "bands.XYZ: not a fund or asset class with a target",
+            ]
+        );
+    }
+}
@@ -1,82 +1,346 @@
-use anyhow::Result;
+use chrono::NaiveDateTime;
+use csv::{ReaderBuilder, Trim};
 use rust_decimal::Decimal;
-use std::fs;
-use std::path::PathBuf;
-use std::str::FromStr;
-
-#[derive(Debug)]
-pub struct Holding {
-    pub symbol: String,
-    pub market_value: Decimal,
+
+use crate::diagnostics::{ParseError, ParseWarning};
+use crate::money::{parse_money, parse_money_with_currency, MoneyFormat};
+use crate::statement::{parse_timestamp, Holding, Statement, StatementParser};
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+
+/// A non-blank CSV row along with the line it starts on in the source file.
+pub struct Row {
+    pub line: u64,
+    pub fields: Vec<String>,
+}
+
+impl Row {
+    fn field(&self, index: usize) -> &str {
+        self.fields.get(index).map(String::as_str).unwrap_or("")
+    }
+
+    fn text(&self, index: Option<usize>) -> Option<String> {
+        index
+            .map(|index| self.field(index))
+            .filter(|text| !text.is_empty())
+            .map(str::to_string)
+    }
 }
 
-pub fn parse_csv(path: &PathBuf) -> Result<(Decimal, Vec<Holding>)> {
-    let content = fs::read_to_string(path)?;
-    let lines: Vec<&str> = content.lines().collect();
+/// Reads typed values out of rows, recording a warning for anything that
+/// cannot be read.
+struct FieldReader<'a> {
+    money: &'a MoneyFormat,
+    warnings: &'a mut Vec<ParseWarning>,
+}
 
-    let mut holdings = Vec::new();
-    let mut cash = Decimal::ZERO;
-    let mut in_data_section = false;
-    let mut symbol_index = None;
-    let mut market_value_index = None;
+impl FieldReader<'_> {
+    fn invalid(&mut self, row: &Row, column: &str, text: &str) {
+        self.warnings.push(ParseWarning::InvalidValue {
+            line: row.line,
+            column: column.to_string(),
+            text: text.to_string(),
+        });
+    }
 
-    for line in lines {
-        let fields: Vec<&str> = line.split(',').collect();
+    /// Parses `column` with `parse`, recording a warning if it is missing or unreadable.
+    fn read<T>(
+        &mut self,
+        row: &Row,
+        index: usize,
+        column: &str,
+        parse: impl Fn(&str, &MoneyFormat) -> Option<T>,
+    ) -> Option<T> {
+        let text = row.field(index);
+        if text.is_empty() {
+            self.warnings.push(ParseWarning::MissingValue {
+                line: row.line,
+                column: column.to_string(),
+            });
+            return None;
+        }
+        let value = parse(text, self.money);
+        if value.is_none() {
+            self.invalid(row, column, text);
+        }
+        value
+    }
 
-        // Look for cash entry in header section
-        if fields.len() >= 2 && fields[0].trim() == "Cash" {
-            if let Ok(value) = Decimal::from_str(fields[1].trim()) {
-                cash = value;
-            }
+    /// Parses the amount in `column`, recording a warning if it is missing or unreadable.
+    fn decimal(&mut self, row: &Row, index: usize, column: &str) -> Option<Decimal> {
+        self.read(row, index, column, parse_money)
+    }
+
+    /// Parses the amount in `column` along with any currency code written after it.
+    fn amount(
+        &mut self,
+        row: &Row,
+        index: usize,
+        column: &str,
+    ) -> Option<(Decimal, Option<String>)> {
+        self.read(row, index, column, parse_money_with_currency)
+    }
+
+    /// Parses an optional amount column. Blank cells are `None`; unreadable
+    /// ones are `None` with a warning.
+    fn optional_decimal(
+        &mut self,
+        row: &Row,
+        index: Option<usize>,
+        column: &str,
+    ) -> Option<Decimal> {
+        let index = index?;
+        if row.field(index).is_empty() {
+            return None;
+        }
+        self.decimal(row, index, column)
+    }
+
+    /// Parses the timestamp in `column`, recording a warning if it is unreadable.
+    fn timestamp(&mut self, row: &Row, index: usize, column: &str) -> Option<NaiveDateTime> {
+        let text = row.field(index);
+        let timestamp = parse_timestamp(text);
+        if timestamp.is_none() {
+            self.invalid(row, column, text);
+        }
+        timestamp
+    }
+}
+
+/// Reads every record of `data` with an RFC 4180 reader: quoted fields, embedded
+/// commas, CRLF line endings and a leading UTF-8 BOM are all handled. Rows may
+/// have differing lengths, and fully blank rows are dropped.
+pub fn read_rows(data: &[u8]) -> Result<Vec<Row>, ParseError> {
+    let data = data.strip_prefix(UTF8_BOM).unwrap_or(data);
+    let mut reader = ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(Trim::All)
+        .from_reader(data);
+
+    let mut rows = Vec::new();
+    for record in reader.records() {
+        let record = record?;
+        if record.iter().all(str::is_empty) {
             continue;
         }
+        rows.push(Row {
+            line: record.position().map(|p| p.line()).unwrap_or(0),
+            fields: record.iter().map(str::to_string).collect(),
+        });
+    }
+    Ok(rows)
+}
 
-        // Check if this is the column header row
-        if fields.len() > 10 && fields[0].trim() == "Symbol" {
-            // Find column indices
-            for (i, field) in fields.iter().enumerate() {
-                match field.trim() {
-                    "Symbol" => symbol_index = Some(i),
-                    "Market Value" => market_value_index = Some(i),
-                    _ => {}
+/// Positions of the known columns in a `Symbol ... Market Value` header row.
+struct Columns {
+    symbol: usize,
+    market_value: usize,
+    market: Option<usize>,
+    description: Option<usize>,
+    quantity: Option<usize>,
+    price: Option<usize>,
+    average_cost: Option<usize>,
+    book_cost: Option<usize>,
+    unrealized_gain: Option<usize>,
+    currency: Option<usize>,
+}
+
+impl Columns {
+    fn from_header(header: &Row) -> Result<Columns, ParseError> {
+        let find = |column: &str| header.fields.iter().position(|field| field == column);
+        let require = |column: &str| {
+            find(column).ok_or_else(|| ParseError::MissingColumn {
+                line: header.line,
+                column: column.to_string(),
+            })
+        };
+        Ok(Columns {
+            symbol: require("Symbol")?,
+            market_value: require("Market Value")?,
+            market: find("Market"),
+            description: find("Description"),
+            quantity: find("Quantity"),
+            price: find("Price"),
+            average_cost: find("Average Cost"),
+            book_cost: find("Book Cost"),
+            unrealized_gain: find("Unrealized $"),
+            currency: find("Currency"),
+        })
+    }
+
+    /// Reads the holding on `row`, or `None` if the row is skipped.
+    fn holding(&self, row: &Row, reader: &mut FieldReader) -> Option<Holding> {
+        let symbol = row.field(self.symbol);
+        if symbol.is_empty() {
+            return None;
+        }
+        let (market_value, currency) = reader.amount(row, self.market_value, "Market Value")?;
+        Some(Holding {
+            symbol: symbol.to_string(),
+            market_value,
+            market: row.text(self.market),
+            description: row.text(self.description),
+            quantity: reader.optional_decimal(row, self.quantity, "Quantity"),
+            price: reader.optional_decimal(row, self.price, "Price"),
+            average_cost: reader.optional_decimal(row, self.average_cost, "Average Cost"),
+            book_cost: reader.optional_decimal(row, self.book_cost, "Book Cost"),
+            unrealized_gain: reader.optional_decimal(row, self.unrealized_gain, "Unrealized $"),
+            currency: row
+                .text(self.currency)
+                .or(currency)
+                .map(|c| c.to_uppercase()),
+        })
+    }
+}
+
+/// The bank holdings export: `As of Date`/`Account`/`Cash` header rows followed
+/// by a `Symbol,Market,Description,...` table of positions.
+pub struct BankCsvParser;
+
+impl BankCsvParser {
+    /// Returns true if `rows` start with the export's summary rows.
+    pub fn detect_rows(rows: &[Row]) -> bool {
+        rows.iter()
+            .take(10)
+            .any(|row| matches!(row.field(0), "As of Date" | "Cash"))
+    }
+
+    /// Parses the summary rows and holdings table from already-read rows.
+    pub fn parse_rows(
+        rows: &[Row],
+        money: &MoneyFormat,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<Statement, ParseError> {
+        let mut reader = FieldReader { money, warnings };
+        let mut statement = Statement::default();
+        let mut cash = None;
+        let mut columns: Option<Columns> = None;
+
+        for row in rows {
+            if let Some(columns) = &columns {
+                // Parse data rows once the column header has been seen
+                statement.holdings.extend(columns.holding(row, &mut reader));
+                continue;
+            }
+
+            // Summary rows before the column header
+            match row.field(0) {
+                "As of Date" => statement.as_of = reader.timestamp(row, 1, "As of Date"),
+                "Account" => statement.account = row.text(Some(1)),
+                "Cash" => {
+                    let (amount, currency) = reader.amount(row, 1, "Cash").unzip();
+                    cash = Some(amount.unwrap_or(Decimal::ZERO));
+                    statement.cash_currency = currency.flatten();
                 }
+                "Investments" => {
+                    statement.investments = reader.optional_decimal(row, Some(1), "Investments")
+                }
+                "Total Value" => {
+                    statement.total_value = reader.optional_decimal(row, Some(1), "Total Value")
+                }
+                "Symbol" => columns = Some(Columns::from_header(row)?),
+                _ => {}
             }
-            in_data_section = true;
-            continue;
         }
 
-        // Parse data rows if we're in the data section
-        if in_data_section && fields.len() > 10 {
-            if let (Some(sym_idx), Some(mv_idx)) = (symbol_index, market_value_index) {
-                if sym_idx < fields.len() && mv_idx < fields.len() {
-                    let symbol = fields[sym_idx].trim();
-                    let market_value_str = fields[mv_idx].trim();
-
-                    // Skip empty symbols and parse market value
-                    if !symbol.is_empty() && !market_value_str.is_empty() {
-                        if let Ok(market_value) = Decimal::from_str(market_value_str) {
-                            holdings.push(Holding {
-                                symbol: symbol.to_string(),
-                                market_value,
-                            });
-                        }
-                    }
-                }
+        if columns.is_none() {
+            return Err(ParseError::MissingHeader {
+                column: "Symbol".to_string(),
+            });
+        }
+        if cash.is_none() {
+            reader.warnings.push(ParseWarning::MissingCash);
+        }
+        statement.cash = cash.unwrap_or(Decimal::ZERO);
+
+        Ok(statement)
+    }
+}
+
+impl StatementParser for BankCsvParser {
+    fn detect(&self, data: &[u8]) -> bool {
+        read_rows(data).is_ok_and(|rows| BankCsvParser::detect_rows(&rows))
+    }
+
+    fn parse(
+        &self,
+        data: &[u8],
+        money: &MoneyFormat,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<Statement, ParseError> {
+        BankCsvParser::parse_rows(&read_rows(data)?, money, warnings)
+    }
+}
+
+/// A plain position list: a `Symbol,Market Value` header where a row with the
+/// symbol `CASH` carries the cash balance. Other known columns are optional.
+pub struct PositionsCsvParser;
+
+impl PositionsCsvParser {
+    /// Returns true if the first of `rows` is a `Symbol ... Market Value` header.
+    pub fn detect_rows(rows: &[Row]) -> bool {
+        rows.first()
+            .map(|header| {
+                header.field(0) == "Symbol" && header.fields.iter().any(|f| f == "Market Value")
+            })
+            .unwrap_or(false)
+    }
+
+    /// Parses the position table from already-read rows.
+    pub fn parse_rows(
+        rows: &[Row],
+        money: &MoneyFormat,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<Statement, ParseError> {
+        let mut reader = FieldReader { money, warnings };
+        let mut rows = rows.iter();
+
+        let header = rows.next().ok_or_else(|| ParseError::MissingHeader {
+            column: "Symbol".to_string(),
+        })?;
+        let columns = Columns::from_header(header)?;
+
+        let mut statement = Statement::default();
+
+        for row in rows {
+            let Some(holding) = columns.holding(row, &mut reader) else {
+                continue;
+            };
+            if holding.symbol.eq_ignore_ascii_case("CASH") {
+                statement.cash += holding.market_value;
+                statement.cash_currency = holding.currency;
+            } else {
+                statement.holdings.push(holding);
             }
         }
+
+        Ok(statement)
     }
+}
 
-    Ok((cash, holdings))
+impl StatementParser for PositionsCsvParser {
+    fn detect(&self, data: &[u8]) -> bool {
+        read_rows(data).is_ok_and(|rows| PositionsCsvParser::detect_rows(&rows))
+    }
+
+    fn parse(
+        &self,
+        data: &[u8],
+        money: &MoneyFormat,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<Statement, ParseError> {
+        PositionsCsvParser::parse_rows(&read_rows(data)?, money, warnings)
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::io::Write;
-    use tempfile::NamedTempFile;
+    use std::str::FromStr;
 
     #[test]
-    fn test_parse_csv() {
+    fn test_parse_bank_csv() {
         let csv_content = r#"As of Date,2025-05-24 14:39:25
 Account,BANK NAME - ACCOUNT NUMBER
 Cash,5600.43
@@ -88,18 +352,254 @@ ABC123,,FUND1,123.456,31.789,56.43,3924.54,6966.62,3042.08,77.51,10.72,,,,,,,,,,
 ABC456,,FUND2,1234.678,34.232,15.343,42265.50,18943.66,-23321.83,-55.18,29.16,,,,,,,,,,,,
 ABC789,,FUND3,1031.324,13.456,32.435,13877.50,33450.99,19573.50,141.04,51.49,,,,,,,,,,,,
 "#;
-        let mut temp_file = NamedTempFile::new().unwrap();
-        write!(temp_file, "{}", csv_content).unwrap();
-        
-        let (cash, holdings) = parse_csv(&temp_file.path().to_path_buf()).unwrap();
-        
-        assert_eq!(cash, Decimal::from_str("5600.43").unwrap());
+        let mut warnings = Vec::new();
+        let statement = BankCsvParser
+            .parse(
+                csv_content.as_bytes(),
+                &MoneyFormat::default(),
+                &mut warnings,
+            )
+            .unwrap();
+        let holdings = &statement.holdings;
+
+        assert!(warnings.is_empty());
+        assert_eq!(statement.as_of, parse_timestamp("2025-05-24 14:39:25"));
+        assert_eq!(
+            statement.account.as_deref(),
+            Some("BANK NAME - ACCOUNT NUMBER")
+        );
+        assert_eq!(statement.cash, Decimal::from_str("5600.43").unwrap());
+        assert_eq!(
+            statement.investments,
+            Some(Decimal::from_str("59361.28").unwrap())
+        );
+        assert_eq!(
+            statement.total_value,
+            Some(Decimal::from_str("64961.71").unwrap())
+        );
         assert_eq!(holdings.len(), 3);
         assert_eq!(holdings[0].symbol, "ABC123");
-        assert_eq!(holdings[0].market_value, Decimal::from_str("6966.62").unwrap());
+        assert_eq!(
+            holdings[0].market_value,
+            Decimal::from_str("6966.62").unwrap()
+        );
         assert_eq!(holdings[1].symbol, "ABC456");
-        assert_eq!(holdings[1].market_value, Decimal::from_str("18943.66").unwrap());
+        assert_eq!(
+            holdings[1].market_value,
+            Decimal::from_str("18943.66").unwrap()
+        );
         assert_eq!(holdings[2].symbol, "ABC789");
-        assert_eq!(holdings[2].market_value, Decimal::from_str("33450.99").unwrap());
+        assert_eq!(
+            holdings[2].market_value,
+            Decimal::from_str("33450.99").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_bank_csv_position_detail() {
+        let csv_content = r#"Cash,0.00
+,
+Symbol,Market,Description,Quantity,Average Cost,Price,Book Cost,Market Value,Unrealized $,Unrealized %
+ABC123,CDN,FUND1,123.456,31.789,56.43,3924.54,6966.62,3042.08,77.51
+ABC456,,,,,,,18943.66,,
+"#;
+        let mut warnings = Vec::new();
+        let Statement { holdings, .. } = BankCsvParser
+            .parse(
+                csv_content.as_bytes(),
+                &MoneyFormat::default(),
+                &mut warnings,
+            )
+            .unwrap();
+
+        assert!(warnings.is_empty());
+        assert_eq!(
+            holdings[0],
+            Holding {
+                symbol: "ABC123".to_string(),
+                market_value: Decimal::from_str("6966.62").unwrap(),
+                market: Some("CDN".to_string()),
+                description: Some("FUND1".to_string()),
+                quantity: Some(Decimal::from_str("123.456").unwrap()),
+                price: Some(Decimal::from_str("56.43").unwrap()),
+                average_cost: Some(Decimal::from_str("31.789").unwrap()),
+                book_cost: Some(Decimal::from_str("3924.54").unwrap()),
+                unrealized_gain: Some(Decimal::from_str("3042.08").unwrap()),
+                currency: None,
+            }
+        );
+        assert_eq!(
+            holdings[1],
+            Holding {
+                symbol: "ABC456".to_string(),
+                market_value: Decimal::from_str("18943.66").unwrap(),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_positions_csv() {
+        let csv_content = "Symbol,Description,Market Value\nCASH,Cash,250.00\nABC123,FUND1,6966.62\n,,\nABC456,FUND2,18943.66\n";
+
+        assert!(PositionsCsvParser.detect(csv_content.as_bytes()));
+        assert!(!BankCsvParser.detect(csv_content.as_bytes()));
+
+        let mut warnings = Vec::new();
+        let Statement { cash, holdings, .. } = PositionsCsvParser
+            .parse(
+                csv_content.as_bytes(),
+                &MoneyFormat::default(),
+                &mut warnings,
+            )
+            .unwrap();
+
+        assert_eq!(cash, Decimal::from_str("250.00").unwrap());
+        assert_eq!(holdings.len(), 2);
+        assert_eq!(holdings[0].symbol, "ABC123");
+        assert_eq!(
+            holdings[1].market_value,
+            Decimal::from_str("18943.66").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_parse_bank_csv_with_quoted_fields() {
+        let csv_content = "\u{feff}As of Date,2025-05-24 14:39:25\r\n\
+Account,\"BANK NAME, ACCOUNT NUMBER\"\r\n\
+Cash,100.00\r\n\
+,\r\n\
+Symbol,Description,Market Value\r\n\
+VTSAX,\"Vanguard Total Stock Market, Admiral\",25006.15\r\n\
+VBTLX,\"Vanguard \"\"Total\"\" Bond\",10470.05\r\n";
+
+        assert!(BankCsvParser.detect(csv_content.as_bytes()));
+
+        let mut warnings = Vec::new();
+        let Statement { cash, holdings, .. } = BankCsvParser
+            .parse(
+                csv_content.as_bytes(),
+                &MoneyFormat::default(),
+                &mut warnings,
+            )
+            .unwrap();
+
+        assert_eq!(cash, Decimal::from(100));
+        assert_eq!(holdings.len(), 2);
+        assert_eq!(holdings[0].symbol, "VTSAX");
+        assert_eq!(
+            holdings[0].market_value,
+            Decimal::from_str("25006.15").unwrap()
+        );
+        assert_eq!(holdings[1].symbol, "VBTLX");
+        assert_eq!(
+            holdings[1].market_value,
+            Decimal::from_str("10470.05").unwrap()
+        );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_bank_csv_currencies() {
+        let csv_content = "Cash,250.00 USD
+,
+Symbol,Description,Market Value,Currency
+VFV,S&P 500 ETF,\"$1,000.00\",CAD
+VTI,Total Market ETF,\"2,000.00 USD\",
+XBB,Bond ETF,500.00,
+";
+        let mut warnings = Vec::new();
+        let statement = BankCsvParser
+            .parse(
+                csv_content.as_bytes(),
+                &MoneyFormat::default(),
+                &mut warnings,
+            )
+            .unwrap();
+
+        assert!(warnings.is_empty());
+        assert_eq!(statement.cash, Decimal::from(250));
+        assert_eq!(statement.cash_currency.as_deref(), Some("USD"));
+        assert_eq!(statement.holdings[0].currency.as_deref(), Some("CAD"));
+        assert_eq!(statement.holdings[1].market_value, Decimal::from(2000));
+        assert_eq!(statement.holdings[1].currency.as_deref(), Some("USD"));
+        assert_eq!(statement.holdings[2].currency, None);
+    }
+
+    #[test]
+    fn test_parse_bank_csv_reports_bad_rows() {
+        let csv_content = "As of Date,2025-05-24 14:39:25
+Cash,about 100
+,
+Symbol,Market,Description,Market Value
+ABC123,,FUND1,6966.62
+ABC456,,FUND2,N/A
+ABC789,,FUND3
+";
+        let mut warnings = Vec::new();
+        let Statement { cash, holdings, .. } = BankCsvParser
+            .parse(
+                csv_content.as_bytes(),
+                &MoneyFormat::default(),
+                &mut warnings,
+            )
+            .unwrap();
+
+        assert_eq!(cash, Decimal::ZERO);
+        assert_eq!(holdings.len(), 1);
+        assert_eq!(
+            warnings,
+            vec![
+                ParseWarning::InvalidValue {
+                    line: 2,
+                    column: "Cash".to_string(),
+                    text: "about 100".to_string(),
+                },
+                ParseWarning::InvalidValue {
+                    line: 6,
+                    column: "Market Value".to_string(),
+                    text: "N/A".to_string(),
+                },
+                ParseWarning::MissingValue {
+                    line: 7,
+                    column: "Market Value".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_bank_csv_errors() {
+        let mut warnings = Vec::new();
+
+        let truncated = "As of Date,2025-05-24 14:39:25\nCash,100.00\n";
+        assert_eq!(
+            BankCsvParser
+                .parse(truncated.as_bytes(), &MoneyFormat::default(), &mut warnings)
+                .unwrap_err(),
+            ParseError::MissingHeader {
+                column: "Symbol".to_string()
+            }
+        );
+
+        let no_market_value = "Cash,100.00\n,\nSymbol,Market,Description\nABC123,,FUND1\n";
+        assert_eq!(
+            BankCsvParser
+                .parse(
+                    no_market_value.as_bytes(),
+                    &MoneyFormat::default(),
+                    &mut warnings
+                )
+                .unwrap_err(),
+            ParseError::MissingColumn {
+                line: 3,
+                column: "Market Value".to_string()
+            }
+        );
+
+        let no_cash = "Symbol,Market,Description,Market Value\nABC123,,FUND1,10.00\n";
+        BankCsvParser
+            .parse(no_cash.as_bytes(), &MoneyFormat::default(), &mut warnings)
+            .unwrap();
+        assert_eq!(warnings, vec![ParseWarning::MissingCash]);
+    }
+}
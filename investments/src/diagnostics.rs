@@ -0,0 +1,125 @@
+use std::fmt;
+
+/// A problem that makes the statement unusable.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// The file is not well-formed for its format (bad CSV quoting, invalid UTF-8, ...).
+    Malformed { line: Option<u64>, message: String },
+    /// No column header row containing `column` was found.
+    MissingHeader { column: String },
+    /// The column header row on `line` does not contain `column`.
+    MissingColumn { line: u64, column: String },
+    /// A required element of a tagged format (such as OFX) was not found.
+    MissingElement { element: String },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Malformed {
+                line: Some(line),
+                message,
+            } => write!(f, "line {}: {}", line, message),
+            ParseError::Malformed {
+                line: None,
+                message,
+            } => write!(f, "{}", message),
+            ParseError::MissingHeader { column } => {
+                write!(f, "no column header row with a '{}' column found", column)
+            }
+            ParseError::MissingColumn { line, column } => {
+                write!(f, "line {}: column header has no '{}' column", line, column)
+            }
+            ParseError::MissingElement { element } => write!(f, "no <{}> element found", element),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl From<csv::Error> for ParseError {
+    fn from(error: csv::Error) -> Self {
+        ParseError::Malformed {
+            line: error.position().map(|p| p.line()),
+            message: error.to_string(),
+        }
+    }
+}
+
+impl From<serde_json::Error> for ParseError {
+    fn from(error: serde_json::Error) -> Self {
+        // The message ends with the position, which is reported separately
+        let message = error.to_string();
+        let message = match message.rsplit_once(" at line ") {
+            Some((message, _)) => message.to_string(),
+            None => message,
+        };
+        ParseError::Malformed {
+            line: Some(error.line() as u64),
+            message,
+        }
+    }
+}
+
+/// A problem that was worked around while parsing, but may mean the parsed
+/// statement does not match the file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseWarning {
+    /// The text in `column` on `line` could not be read, so the value was skipped.
+    InvalidValue {
+        line: u64,
+        column: String,
+        text: String,
+    },
+    /// The row on `line` has a symbol but nothing in `column`, so it was skipped.
+    MissingValue { line: u64, column: String },
+    /// The statement has no cash row, so cash is assumed to be zero.
+    MissingCash,
+}
+
+impl fmt::Display for ParseWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseWarning::InvalidValue { line, column, text } => write!(
+                f,
+                "line {}, column '{}': cannot parse '{}', value skipped",
+                line, column, text
+            ),
+            ParseWarning::MissingValue { line, column } => {
+                write!(
+                    f,
+                    "line {}, column '{}': value is missing, row skipped",
+                    line, column
+                )
+            }
+            ParseWarning::MissingCash => write!(f, "no cash row found, assuming zero cash"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diagnostic_messages() {
+        let warning = ParseWarning::InvalidValue {
+            line: 9,
+            column: "Market Value".to_string(),
+            text: "N/A".to_string(),
+        };
+        assert_eq!(
+            warning.to_string(),
+            "line 9, column 'Market Value': cannot parse 'N/A', value skipped"
+        );
+
+        let error = ParseError::MissingColumn {
+            line: 7,
+            column: "Market Value".to_string(),
+        };
+        assert_eq!(
+            error.to_string(),
+            "line 7: column header has no 'Market Value' column"
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use chrono::{Duration, NaiveDateTime};
+use serde::Deserialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum StaleAction {
+    /// Refuse to generate orders
+    #[default]
+    Error,
+    /// Print a warning and generate orders anyway
+    Warn,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FreshnessConfig {
+    /// Oldest statement, in days, that orders may be generated from. Unset
+    /// means statements are never considered stale.
+    pub max_statement_age: Option<u32>,
+    pub on_stale: StaleAction,
+}
+
+/// Returns why the statement is too old to rebalance from at `now`, or `None`
+/// if it is recent enough or no maximum age is configured.
+pub fn stale_reason(
+    as_of: Option<NaiveDateTime>,
+    now: NaiveDateTime,
+    config: &FreshnessConfig,
+) -> Option<String> {
+    let max_days = config.max_statement_age?;
+    let Some(as_of) = as_of else {
+        return Some(format!(
+            "statement has no As of Date and max_statement_age is {} day(s); pass --as-of to set one",
+            max_days
+        ));
+    };
+
+    let age = now - as_of;
+    (age > Duration::days(max_days.into())).then(|| {
+        format!(
+            "statement is {:.1} days old (as of {}), older than max_statement_age of {} day(s)",
+            age.num_minutes() as f64 / (24.0 * 60.0),
+            as_of,
+            max_days
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statement::parse_timestamp;
+
+    #[test]
+    fn test_stale_reason() {
+        let now = parse_timestamp("2025-05-28 09:00:00").unwrap();
+        let config = FreshnessConfig {
+            max_statement_age: Some(3),
+            ..Default::default()
+        };
+
+        let recent = parse_timestamp("2025-05-25 14:39:25");
+        assert_eq!(stale_reason(recent, now, &config), None);
+
+        let old = parse_timestamp("2025-05-24 14:39:25");
+        assert_eq!(
+            stale_reason(old, now, &config).unwrap(),
+            "statement is 3.8 days old (as of 2025-05-24 14:39:25), older than max_statement_age of 3 day(s)"
+        );
+
+        assert!(stale_reason(None, now, &config)
+            .unwrap()
+            .contains("no As of Date"));
+    }
+
+    #[test]
+    fn test_no_max_age_never_stale() {
+        let now = parse_timestamp("2030-01-01").unwrap();
+        let config = FreshnessConfig::default();
+
+        assert_eq!(
+            stale_reason(parse_timestamp("2025-05-24"), now, &config),
+            None
+        );
+        assert_eq!(stale_reason(None, now, &config), None);
+    }
+}
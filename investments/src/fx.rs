@@ -0,0 +1,231 @@
+use anyhow::Result;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use crate::statement::{Holding, Statement};
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct CurrencyConfig {
+    /// Currency that targets are computed in, and that amounts without a
+    /// currency of their own are taken to be in. Defaults to `CAD`
+    pub base_currency: String,
+    /// CSV file of `Currency,Rate` rows, where `Rate` is the value of one unit
+    /// of the currency in the base currency. Relative paths are resolved
+    /// against the config file's directory.
+    pub fx_rates: Option<PathBuf>,
+    /// Trading currency of funds that the statement does not report one for
+    pub funds: HashMap<String, String>,
+}
+
+impl Default for CurrencyConfig {
+    fn default() -> Self {
+        CurrencyConfig {
+            base_currency: "CAD".to_string(),
+            fx_rates: None,
+            funds: HashMap::new(),
+        }
+    }
+}
+
+impl CurrencyConfig {
+    /// The currency `symbol` is traded in: the statement's currency if it
+    /// holds the fund, then the configured one, then the base currency.
+    pub fn trading_currency<'a>(&'a self, symbol: &str, holdings: &'a [Holding]) -> &'a str {
+        holdings
+            .iter()
+            .find(|h| h.symbol == symbol)
+            .and_then(|h| h.currency.as_deref())
+            .or_else(|| self.funds.get(symbol).map(String::as_str))
+            .unwrap_or(&self.base_currency)
+    }
+}
+
+/// Exchange rates into a base currency.
+#[derive(Debug, Clone)]
+pub struct FxRates {
+    base: String,
+    rates: HashMap<String, Decimal>,
+}
+
+impl FxRates {
+    pub fn new(base: &str) -> Self {
+        FxRates {
+            base: base.to_string(),
+            rates: HashMap::new(),
+        }
+    }
+
+    /// Loads a `Currency,Rate` CSV file of rates into `base`.
+    pub fn load(path: &Path, base: &str) -> Result<Self> {
+        let mut reader = csv::ReaderBuilder::new()
+            .trim(csv::Trim::All)
+            .from_path(path)
+            .map_err(|e| anyhow::anyhow!("Cannot read FX rates {:?}: {}", path, e))?;
+
+        let mut fx = FxRates::new(base);
+        for record in reader.records() {
+            let record = record?;
+            let line = record.position().map(|p| p.line()).unwrap_or(0);
+            let (Some(currency), Some(rate)) = (record.get(0), record.get(1)) else {
+                return Err(anyhow::anyhow!(
+                    "{:?} line {}: expected Currency,Rate",
+                    path,
+                    line
+                ));
+            };
+            let rate: Decimal = rate.parse().map_err(|_| {
+                anyhow::anyhow!("{:?} line {}: invalid rate '{}'", path, line, rate)
+            })?;
+            if rate <= Decimal::ZERO {
+                return Err(anyhow::anyhow!(
+                    "{:?} line {}: rate for {} must be positive",
+                    path,
+                    line,
+                    currency
+                ));
+            }
+            fx.rates.insert(currency.to_uppercase(), rate);
+        }
+        Ok(fx)
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    fn rate(&self, currency: &str) -> Result<Decimal> {
+        if currency.eq_ignore_ascii_case(&self.base) {
+            return Ok(Decimal::ONE);
+        }
+        self.rates
+            .get(&currency.to_uppercase())
+            .copied()
+            .ok_or_else(|| {
+                anyhow::anyhow!(
+                    "No FX rate from {} to {}. Add it to the fx_rates file",
+                    currency,
+                    self.base
+                )
+            })
+    }
+
+    pub fn to_base(&self, amount: Decimal, currency: &str) -> Result<Decimal> {
+        Ok(amount * self.rate(currency)?)
+    }
+
+    pub fn to_currency(&self, amount: Decimal, currency: &str) -> Result<Decimal> {
+        Ok(amount / self.rate(currency)?)
+    }
+}
+
+/// Converts the statement's cash and holdings into the base currency.
+pub fn statement_in_base(
+    statement: &Statement,
+    config: &CurrencyConfig,
+    fx: &FxRates,
+) -> Result<(Decimal, Vec<Holding>)> {
+    let cash_currency = statement.cash_currency.as_deref().unwrap_or(fx.base());
+    let cash = fx.to_base(statement.cash, cash_currency)?;
+
+    let holdings = statement
+        .holdings
+        .iter()
+        .map(|holding| {
+            let currency = config.trading_currency(&holding.symbol, &statement.holdings);
+            Ok(Holding {
+                market_value: fx.to_base(holding.market_value, currency)?,
+                currency: Some(fx.base().to_string()),
+                ..holding.clone()
+            })
+        })
+        .collect::<Result<_>>()?;
+
+    Ok((cash, holdings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn usd_rates() -> FxRates {
+        let mut fx = FxRates::new("CAD");
+        fx.rates
+            .insert("USD".to_string(), Decimal::from_str("1.25").unwrap());
+        fx
+    }
+
+    #[test]
+    fn test_fx_conversion() {
+        let fx = usd_rates();
+
+        assert_eq!(
+            fx.to_base(Decimal::from(100), "USD").unwrap(),
+            Decimal::from(125)
+        );
+        assert_eq!(
+            fx.to_currency(Decimal::from(125), "usd").unwrap(),
+            Decimal::from(100)
+        );
+        assert_eq!(
+            fx.to_base(Decimal::from(100), "CAD").unwrap(),
+            Decimal::from(100)
+        );
+        assert!(fx.to_base(Decimal::from(100), "EUR").is_err());
+    }
+
+    #[test]
+    fn test_load_fx_rates() {
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("fx.csv");
+        std::fs::write(&path, "Currency,Rate\nUSD,1.25\neur, 1.5\n").unwrap();
+
+        let fx = FxRates::load(&path, "CAD").unwrap();
+        assert_eq!(
+            fx.to_base(Decimal::from(2), "EUR").unwrap(),
+            Decimal::from(3)
+        );
+
+        std::fs::write(&path, "Currency,Rate\nUSD,-1\n").unwrap();
+        assert!(FxRates::load(&path, "CAD").is_err());
+    }
+
+    #[test]
+    fn test_statement_in_base() {
+        let config = CurrencyConfig {
+            funds: HashMap::from([("VFV".to_string(), "CAD".to_string())]),
+            ..Default::default()
+        };
+        let statement = Statement {
+            cash: Decimal::from(100),
+            cash_currency: Some("USD".to_string()),
+            holdings: vec![
+                Holding {
+                    symbol: "VTI".to_string(),
+                    market_value: Decimal::from(1000),
+                    currency: Some("USD".to_string()),
+                    ..Default::default()
+                },
+                Holding {
+                    symbol: "VFV".to_string(),
+                    market_value: Decimal::from(1000),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+
+        let (cash, holdings) = statement_in_base(&statement, &config, &usd_rates()).unwrap();
+
+        assert_eq!(cash, Decimal::from(125));
+        assert_eq!(holdings[0].market_value, Decimal::from(1250));
+        assert_eq!(holdings[0].currency.as_deref(), Some("CAD"));
+        assert_eq!(holdings[1].market_value, Decimal::from(1000));
+        assert_eq!(config.trading_currency("VTI", &statement.holdings), "USD");
+        assert_eq!(config.trading_currency("VFV", &statement.holdings), "CAD");
+        assert_eq!(config.trading_currency("XBB", &statement.holdings), "CAD");
+    }
+}
@@ -0,0 +1,167 @@
+use rust_decimal::Decimal;
+use serde::Deserialize;
+
+use crate::diagnostics::{ParseError, ParseWarning};
+use crate::money::MoneyFormat;
+use crate::statement::{parse_timestamp, Holding, Statement, StatementParser};
+
+/// A statement written by another program. Amounts may be JSON numbers, read
+/// exactly as written (serde_json's `arbitrary_precision`), or decimal
+/// strings; everything except `cash` and `holdings` is optional.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonStatement {
+    as_of: Option<String>,
+    account: Option<String>,
+    cash: Decimal,
+    cash_currency: Option<String>,
+    investments: Option<Decimal>,
+    total_value: Option<Decimal>,
+    holdings: Vec<JsonHolding>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct JsonHolding {
+    symbol: String,
+    market_value: Decimal,
+    market: Option<String>,
+    description: Option<String>,
+    quantity: Option<Decimal>,
+    price: Option<Decimal>,
+    average_cost: Option<Decimal>,
+    book_cost: Option<Decimal>,
+    unrealized_gain: Option<Decimal>,
+    currency: Option<String>,
+}
+
+/// A JSON statement, for portfolios generated by other tools. Since the file
+/// is machine-written, anything that does not match the schema is an error
+/// rather than a warning.
+pub struct JsonParser;
+
+impl StatementParser for JsonParser {
+    fn detect(&self, data: &[u8]) -> bool {
+        let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+        data.iter()
+            .find(|b| !b.is_ascii_whitespace())
+            .is_some_and(|&b| b == b'{')
+    }
+
+    fn parse(
+        &self,
+        data: &[u8],
+        _money: &MoneyFormat,
+        _warnings: &mut Vec<ParseWarning>,
+    ) -> Result<Statement, ParseError> {
+        let data = data.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(data);
+        let json: JsonStatement = serde_json::from_slice(data)?;
+
+        let as_of = match &json.as_of {
+            Some(text) => Some(parse_timestamp(text).ok_or_else(|| ParseError::Malformed {
+                line: None,
+                message: format!("as_of: cannot parse '{}' as a date", text),
+            })?),
+            None => None,
+        };
+        let holdings = json
+            .holdings
+            .into_iter()
+            .map(|holding| Holding {
+                symbol: holding.symbol,
+                market_value: holding.market_value,
+                market: holding.market,
+                description: holding.description,
+                quantity: holding.quantity,
+                price: holding.price,
+                average_cost: holding.average_cost,
+                book_cost: holding.book_cost,
+                unrealized_gain: holding.unrealized_gain,
+                currency: holding.currency.map(|c| c.to_uppercase()),
+            })
+            .collect();
+
+        Ok(Statement {
+            as_of,
+            account: json.account,
+            cash: json.cash,
+            cash_currency: json.cash_currency.map(|c| c.to_uppercase()),
+            investments: json.investments,
+            total_value: json.total_value,
+            holdings,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn parse(json: &str) -> Result<Statement, ParseError> {
+        JsonParser.parse(json.as_bytes(), &MoneyFormat::default(), &mut Vec::new())
+    }
+
+    #[test]
+    fn test_parse_json() {
+        let json = r#"{
+  "as_of": "2025-05-24 14:39:25",
+  "account": "BANK NAME - ACCOUNT NUMBER",
+  "cash": "1000.00",
+  "cash_currency": "cad",
+  "total_value": 10000.10,
+  "holdings": [
+    {"symbol": "ABC123", "market_value": 6000.10, "quantity": "100", "currency": "usd"},
+    {"symbol": "ABC456", "market_value": "3000.00", "description": "FUND2", "book_cost": 12345678901234.5678}
+  ]
+}"#;
+        assert!(JsonParser.detect(json.as_bytes()));
+
+        let statement = parse(json).unwrap();
+        assert_eq!(statement.as_of, parse_timestamp("2025-05-24 14:39:25"));
+        assert_eq!(
+            statement.account.as_deref(),
+            Some("BANK NAME - ACCOUNT NUMBER")
+        );
+        assert_eq!(statement.cash, Decimal::from(1000));
+        assert_eq!(statement.cash_currency.as_deref(), Some("CAD"));
+        assert_eq!(statement.investments, None);
+        assert_eq!(
+            statement.total_value,
+            Some(Decimal::from_str("10000.10").unwrap())
+        );
+        assert_eq!(statement.holdings.len(), 2);
+        // Numbers keep their written precision, trailing zeros included
+        assert_eq!(
+            statement.holdings[0].market_value,
+            Decimal::from_str("6000.10").unwrap()
+        );
+        assert_eq!(statement.holdings[0].market_value.scale(), 2);
+        assert_eq!(statement.total_value.map(|v| v.scale()), Some(2));
+        // More digits than an f64 holds
+        assert_eq!(
+            statement.holdings[1].book_cost,
+            Some(Decimal::from_str("12345678901234.5678").unwrap())
+        );
+        assert_eq!(statement.holdings[0].quantity, Some(Decimal::from(100)));
+        assert_eq!(statement.holdings[0].currency.as_deref(), Some("USD"));
+        assert_eq!(statement.holdings[1].description.as_deref(), Some("FUND2"));
+    }
+
+    #[test]
+    fn test_parse_json_errors() {
+        let error = parse("{\n  \"cash\": 1,\n  \"holdings\": [{\"symbol\": \"ABC\", \"market_value\": \"N/A\"}]\n}")
+            .unwrap_err();
+        assert!(matches!(error, ParseError::Malformed { line: Some(3), .. }));
+
+        // Misspelled fields are rejected rather than silently ignored
+        let error = parse(r#"{"cash": 1, "holdings": [], "totalvalue": 5}"#).unwrap_err();
+        assert!(error.to_string().contains("unknown field `totalvalue`"));
+
+        let error = parse(r#"{"as_of": "yesterday", "cash": 1, "holdings": []}"#).unwrap_err();
+        assert!(error.to_string().contains("as_of"));
+
+        assert!(parse(r#"{"holdings": []}"#).is_err());
+        assert!(!JsonParser.detect(b"Symbol,Market Value\n"));
+    }
+}
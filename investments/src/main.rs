@@ -1,78 +1,508 @@
 use anyhow::Result;
 use clap::Parser;
+use indexmap::IndexMap;
 use rust_decimal::Decimal;
 use serde::Deserialize;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+mod allocation;
+mod bands;
 mod csv_parser;
-use csv_parser::{parse_csv, Holding};
+mod diagnostics;
+mod freshness;
+mod fx;
+mod json_parser;
+mod money;
+mod ofx_parser;
+mod rebalance;
+mod reconcile;
+mod settlement;
+mod shares;
+mod spreadsheet_parser;
+mod statement;
+use allocation::{AssetClass, CashReserve, Mode, TargetsConfig};
+use bands::BandsConfig;
+use chrono::{Local, NaiveDate, NaiveDateTime};
+use freshness::{stale_reason, FreshnessConfig, StaleAction};
+use fx::{statement_in_base, CurrencyConfig, FxRates};
+use money::MoneyFormat;
+use rebalance::{
+    calculate_orders, planned_cash, round_to_cents, sequence_orders, Account, LocationRule, Order,
+    OrdersConfig,
+};
+use reconcile::{reconcile, ReconciliationConfig};
+use settlement::{schedule_orders, SettlementConfig};
+use shares::{size_orders, Rounding};
+use statement::{parse_statement, Holding, Statement, StatementFormat};
 
 #[derive(Parser)]
 #[command(name = "portfolio-rebalancer")]
 #[command(about = "A simple tool to generate buy/sell orders for portfolio rebalancing")]
 struct Args {
-    /// Input CSV file from bank
-    #[arg(short, long)]
-    input: PathBuf,
+    /// Input statement file from bank, or - to read it from stdin. Repeat for
+    /// each account of a household, tagged as ID:TYPE=PATH (e.g. rrsp:rrsp=rrsp.csv)
+    #[arg(short, long, required = true, value_parser = parse_input)]
+    input: Vec<Input>,
+
+    /// Statement format (detected from the file header when omitted)
+    #[arg(short, long, value_enum)]
+    format: Option<StatementFormat>,
+
+    /// Fail instead of continuing when the statement has parse warnings
+    #[arg(long)]
+    strict: bool,
+
+    /// Generate orders even if the statement totals do not match the parsed rows
+    #[arg(long)]
+    skip_reconciliation: bool,
+
+    /// Use this As of Date (e.g. "2025-05-24 14:39:25") instead of the statement's own
+    #[arg(long, value_parser = parse_as_of)]
+    as_of: Option<NaiveDateTime>,
+
+    /// FX rates CSV (Currency,Rate) into the base currency, overriding the config's fx_rates
+    #[arg(long)]
+    fx_rates: Option<PathBuf>,
 
     /// Configuration TOML file with target allocations (defaults to config.toml in input directory)
     #[arg(short, long)]
     config: Option<PathBuf>,
 
-    /// Output CSV file for buy/sell orders (defaults to input filename with -orders suffix)
+    /// Which orders to generate: full rebalance, buy-only (spend free cash
+    /// without selling) or sell-only (raise missing cash without buying)
+    #[arg(long, value_enum, default_value_t = Mode::Full)]
+    mode: Mode,
+
+    /// Money added before rebalancing, in the base currency, as AMOUNT or as
+    /// ID=AMOUNT for one account of a household (defaults to the first)
+    #[arg(long, value_parser = parse_cash_flow)]
+    contribute: Vec<CashFlow>,
+
+    /// Money taken out before rebalancing, in the base currency, as AMOUNT or
+    /// as ID=AMOUNT for one account of a household (defaults to the first)
+    #[arg(long, value_parser = parse_cash_flow)]
+    withdraw: Vec<CashFlow>,
+
+    /// Date the orders are placed (e.g. 2025-05-26), used to schedule them
+    /// around settlement. Defaults to today
+    #[arg(long, value_parser = parse_trade_date)]
+    trade_date: Option<NaiveDate>,
+
+    /// Output CSV file for buy/sell orders (defaults to input filename with -orders suffix,
+    /// or household-orders.csv for several inputs)
     #[arg(short, long)]
     output: Option<PathBuf>,
 }
 
-#[derive(Debug, Deserialize)]
-struct Config {
-    funds: HashMap<String, Decimal>,
+/// Money added with `--contribute` or taken out with `--withdraw`, optionally
+/// for one account as `ID=AMOUNT`.
+#[derive(Debug, Clone, PartialEq)]
+struct CashFlow {
+    account: Option<String>,
+    amount: Decimal,
 }
 
+/// `--input` value that reads the statement from stdin
+const STDIN: &str = "-";
 
+/// A statement given with `--input`, optionally tagged with the account it
+/// belongs to as `ID:TYPE=PATH` or `ID=PATH`.
+#[derive(Debug, Clone, PartialEq)]
+struct Input {
+    account: Option<String>,
+    kind: Option<String>,
+    path: PathBuf,
+}
 
-#[derive(Debug)]
-struct Order {
-    fund: String,
-    action: String,
-    amount: Decimal,
+impl Input {
+    /// The account id orders are reported under: the tag, or else the file name.
+    fn account_id(&self) -> String {
+        if let Some(account) = &self.account {
+            return account.clone();
+        }
+        if self.path == Path::new(STDIN) {
+            return "stdin".to_string();
+        }
+        self.path
+            .file_stem()
+            .unwrap_or(self.path.as_os_str())
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+struct Config {
+    /// Target percentage per fund, for portfolios without asset classes
+    #[serde(default)]
+    funds: IndexMap<String, Decimal>,
+    /// Target percentages per asset class, split into the funds that hold them
+    #[serde(default)]
+    asset_classes: IndexMap<String, AssetClass>,
+    #[serde(default)]
+    targets: TargetsConfig,
+    /// Cash held back before the targets are applied
+    #[serde(default)]
+    cash_reserve: CashReserve,
+    /// Drift allowed before a class is traded
+    #[serde(default)]
+    bands: BandsConfig,
+    #[serde(default)]
+    reconciliation: ReconciliationConfig,
+    #[serde(default)]
+    freshness: FreshnessConfig,
+    /// How amounts are written, per statement format
+    #[serde(default)]
+    formats: HashMap<StatementFormat, MoneyFormat>,
+    #[serde(default)]
+    currency: CurrencyConfig,
+    /// Preferred account types per asset class, in priority order
+    #[serde(default)]
+    asset_location: Vec<LocationRule>,
+    /// How each fund's orders are rounded to shares
+    #[serde(default)]
+    shares: HashMap<String, Rounding>,
+    /// How the orders file is sorted
+    #[serde(default)]
+    orders: OrdersConfig,
+    /// Business days trades take to settle
+    #[serde(default)]
+    settlement: SettlementConfig,
 }
 
 fn main() -> Result<()> {
     let args = Args::parse();
 
-    let config_path = determine_config_path(&args.input, args.config)?;
-    let output_path = determine_output_path(args.output, &args.input)?;
+    let first_input = &args.input[0].path;
+    let config_path = determine_config_path(first_input, args.config.clone(), Path::new(""))?;
+    let output_path = if args.input.len() > 1 && args.output.is_none() {
+        determine_output_path(None, first_input)?.with_file_name("household-orders.csv")
+    } else {
+        determine_output_path(args.output.clone(), first_input)?
+    };
     let config = load_config(&config_path)?;
-    println!("Loaded config with {} funds", config.funds.len());
+    let classes = allocation::classes(&config)?;
+    println!(
+        "Loaded config with {} funds",
+        classes.iter().map(|c| c.funds.len()).sum::<usize>()
+    );
+
+    let mut account_ids = HashSet::new();
+    for input in &args.input {
+        if !account_ids.insert(input.account_id()) {
+            return Err(anyhow::anyhow!(
+                "Account '{}' is given more than once. Tag each statement as ID:TYPE=PATH",
+                input.account_id()
+            ));
+        }
+    }
+    if args
+        .input
+        .iter()
+        .filter(|i| i.path == Path::new(STDIN))
+        .count()
+        > 1
+    {
+        return Err(anyhow::anyhow!("Only one statement can be read from stdin"));
+    }
 
-    let (cash, holdings) = parse_csv(&args.input)?;
-    println!("Cash: ${:.2}", cash);
-    println!("Found {} holdings", holdings.len());
+    let base_currency = &config.currency.base_currency;
+    let fx_rates_path = args.fx_rates.clone().or_else(|| {
+        let path = config.currency.fx_rates.as_ref()?;
+        Some(config_path.parent().unwrap_or(Path::new("")).join(path))
+    });
+    let fx = match fx_rates_path {
+        Some(path) => FxRates::load(&path, base_currency)?,
+        None => FxRates::new(base_currency),
+    };
+
+    let mut statements = Vec::new();
+    for input in &args.input {
+        statements.push(load_statement(input, &args, &config, &fx)?);
+    }
 
-    let orders = calculate_orders(&config, cash, &holdings)?;
+    let mut accounts = Vec::new();
+    for (input, statement) in args.input.iter().zip(&statements) {
+        let (cash, holdings) = statement_in_base(statement, &config.currency, &fx)?;
+        accounts.push(Account {
+            id: input.account_id(),
+            kind: input.kind.clone(),
+            cash,
+            holdings,
+        });
+    }
+    for (index, rule) in config.asset_location.iter().enumerate() {
+        for (i, kind) in rule.accounts.iter().enumerate() {
+            if !accounts.iter().any(|a| a.is_kind(kind)) {
+                eprintln!(
+                    "Warning: asset_location[{}].accounts[{}]: no --input has account type '{}'",
+                    index, i, kind
+                );
+            }
+        }
+    }
+    let total_value: Decimal = accounts
+        .iter()
+        .map(|a| a.cash + a.holdings.iter().map(|h| h.market_value).sum::<Decimal>())
+        .sum();
+    println!("Portfolio value: ${:.2} {}", total_value, base_currency);
+
+    // Cash flows are applied to the reconciled balances, so the statement
+    // itself never has to be edited
+    let household = args.input.len() > 1;
+    let mut summary = Vec::new();
+    let mut flow = Decimal::ZERO;
+    let cash_flows = args.contribute.iter().map(|f| ("Contribution", f));
+    let cash_flows = cash_flows.chain(args.withdraw.iter().map(|f| ("Withdrawal", f)));
+    for (label, cash_flow) in cash_flows {
+        let account = match &cash_flow.account {
+            Some(id) => accounts
+                .iter_mut()
+                .find(|a| &a.id == id)
+                .ok_or_else(|| anyhow::anyhow!("{} for unknown account '{}'", label, id))?,
+            None => &mut accounts[0],
+        };
+        let amount = if label == "Withdrawal" {
+            -cash_flow.amount
+        } else {
+            cash_flow.amount
+        };
+        account.cash += amount;
+        flow += amount;
+        println!(
+            "{}: ${:.2} {} (account {})",
+            label, cash_flow.amount, base_currency, account.id
+        );
+        let mut row = vec![
+            label.to_string(),
+            format!("{:.2}", cash_flow.amount),
+            base_currency.clone(),
+        ];
+        if household {
+            row.push(account.id.clone());
+        }
+        summary.push(row);
+    }
+
+    let plan = calculate_orders(&config, &accounts, args.mode, flow)?;
+    if plan.reserve > Decimal::ZERO {
+        println!("Cash reserve: ${:.2} {}", plan.reserve, base_currency);
+        summary.push(vec![
+            "Cash Reserve".to_string(),
+            format!("{:.2}", plan.reserve),
+            base_currency.clone(),
+        ]);
+    }
+    for holding in &plan.unmanaged {
+        let action = if holding.liquidated {
+            "selling all of it"
+        } else {
+            "leaving it out of the portfolio"
+        };
+        eprintln!(
+            "Warning: {} has no target in the config; {}",
+            holding, action
+        );
+    }
+    for shortfall in &plan.shortfalls {
+        eprintln!("Warning: {}", shortfall);
+    }
+    let all_holdings: Vec<Holding> = statements
+        .iter()
+        .flat_map(|s| s.holdings.iter().cloned())
+        .collect();
+    let mut orders = orders_in_trading_currency(plan.orders, &config.currency, &all_holdings, &fx)?;
+    let holdings_by_account = args
+        .input
+        .iter()
+        .zip(&statements)
+        .map(|(input, statement)| (input.account_id(), statement.holdings.clone()))
+        .collect();
+    for warning in size_orders(&mut orders, &config.shares, &holdings_by_account, &fx)? {
+        eprintln!("Warning: {}", warning);
+    }
+    let planned = planned_cash(&orders);
+    round_to_cents(&mut orders, &planned)?;
+    sequence_orders(
+        &mut orders,
+        config.orders.sort_by,
+        &allocation::fund_order(&config),
+        &fx,
+    )?;
+    let cash = accounts.iter().map(|a| (a.id.clone(), a.cash)).collect();
+    let trade_date = args.trade_date.unwrap_or_else(|| Local::now().date_naive());
+    schedule_orders(&mut orders, &cash, &config.settlement, trade_date, &fx)?;
     println!("Generated {} orders", orders.len());
+    let waiting: Vec<&Order> = orders
+        .iter()
+        .filter(|o| o.schedule.is_some_and(|s| s.phase == 2))
+        .collect();
+    if let Some(last) = waiting
+        .iter()
+        .filter_map(|o| o.schedule)
+        .max_by_key(|s| s.trade_date)
+    {
+        println!(
+            "Phase 2: {} buy(s) wait for sales to settle, until {}",
+            waiting.len(),
+            last.trade_date
+        );
+    }
 
-    write_orders(&output_path, &orders)?;
+    let sources: Vec<(Input, Statement)> = args.input.iter().cloned().zip(statements).collect();
+    write_orders(&output_path, &sources, &summary, &orders)?;
     println!("Orders written to {:?}", output_path);
 
     Ok(())
 }
 
-fn determine_output_path(
-    path_buf: Option<PathBuf>,
-    path: &PathBuf,
-) -> Result<PathBuf, anyhow::Error> {
+/// Reads, parses and checks one statement, printing its summary.
+fn load_statement(input: &Input, args: &Args, config: &Config, fx: &FxRates) -> Result<Statement> {
+    if args.input.len() > 1 {
+        println!(
+            "Statement {:?} (account {})",
+            input.path,
+            input.account_id()
+        );
+    }
+    let data = read_input(&input.path)?;
+    let mut warnings = Vec::new();
+    let (format, mut statement) =
+        parse_statement(&data, args.format, &config.formats, &mut warnings)?;
+    for warning in &warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    if args.strict && !warnings.is_empty() {
+        return Err(anyhow::anyhow!(
+            "Statement {:?} has {} parse warning(s) and --strict is set",
+            input.path,
+            warnings.len()
+        ));
+    }
+    println!("Parsed statement as {}", format);
+    if args.as_of.is_some() {
+        statement.as_of = args.as_of;
+    }
+    if let Some(account) = &statement.account {
+        println!("Account: {}", account);
+    }
+    if let Some(as_of) = statement.as_of {
+        println!("As of: {}", as_of);
+    }
+    match &statement.cash_currency {
+        Some(currency) => println!("Cash: ${:.2} {}", statement.cash, currency),
+        None => println!("Cash: ${:.2}", statement.cash),
+    }
+    println!("Found {} holdings", statement.holdings.len());
+
+    let mismatches = reconcile(&statement, &config.reconciliation, &config.currency, fx)?;
+    for mismatch in &mismatches {
+        eprintln!("Reconciliation failed: {}", mismatch);
+    }
+    if !mismatches.is_empty() && !args.skip_reconciliation {
+        return Err(anyhow::anyhow!(
+            "Statement {:?} does not reconcile with its reported totals. \
+             Check for missing or duplicated rows, or rerun with --skip-reconciliation",
+            input.path
+        ));
+    }
+
+    let now = Local::now().naive_local();
+    if let Some(reason) = stale_reason(statement.as_of, now, &config.freshness) {
+        match config.freshness.on_stale {
+            StaleAction::Error => {
+                return Err(anyhow::anyhow!(
+                    "Refusing to generate orders: {}. Download a fresh statement or \
+                     raise max_statement_age",
+                    reason
+                ));
+            }
+            StaleAction::Warn => {
+                eprintln!("**********************************************************");
+                eprintln!("WARNING: {}.", reason);
+                eprintln!("Prices may have moved since; review orders before placing.");
+                eprintln!("**********************************************************");
+            }
+        }
+    }
+
+    Ok(statement)
+}
+
+/// Parses an `--input` value: a path, optionally tagged `ID:TYPE=PATH` or `ID=PATH`.
+fn parse_input(text: &str) -> Result<Input, String> {
+    let tagged = text.split_once('=').and_then(|(tag, path)| {
+        let (account, kind) = match tag.split_once(':') {
+            Some((account, kind)) => (account, Some(kind)),
+            None => (tag, None),
+        };
+        (allocation::is_name(account) && kind.is_none_or(allocation::is_name)).then(|| Input {
+            account: Some(account.to_string()),
+            kind: kind.map(str::to_lowercase),
+            path: PathBuf::from(path),
+        })
+    });
+    let input = tagged.unwrap_or_else(|| Input {
+        account: None,
+        kind: None,
+        path: PathBuf::from(text),
+    });
+    if input.path.as_os_str().is_empty() {
+        return Err(format!("no statement path in '{}'", text));
+    }
+    Ok(input)
+}
+
+fn parse_cash_flow(text: &str) -> Result<CashFlow, String> {
+    let (account, amount) = match text.split_once('=') {
+        Some((account, amount)) => (Some(account.to_string()), amount),
+        None => (None, text),
+    };
+    let amount = Decimal::from_str(amount.trim())
+        .map_err(|_| format!("expected AMOUNT or ID=AMOUNT, got '{}'", text))?;
+    if amount <= Decimal::ZERO {
+        return Err(format!("amount must be positive, got '{}'", text));
+    }
+    Ok(CashFlow { account, amount })
+}
+
+fn read_input(path: &Path) -> Result<Vec<u8>> {
+    if path == Path::new(STDIN) {
+        let mut data = Vec::new();
+        std::io::stdin().read_to_end(&mut data)?;
+        return Ok(data);
+    }
+    fs::read(path).map_err(|e| anyhow::anyhow!("Cannot read statement {:?}: {}", path, e))
+}
+
+fn parse_trade_date(text: &str) -> Result<NaiveDate, String> {
+    NaiveDate::parse_from_str(text, "%Y-%m-%d")
+        .map_err(|_| format!("expected YYYY-MM-DD, got '{}'", text))
+}
+
+fn parse_as_of(text: &str) -> Result<NaiveDateTime, String> {
+    statement::parse_timestamp(text)
+        .ok_or_else(|| format!("expected YYYY-MM-DD or YYYY-MM-DD HH:MM:SS, got '{}'", text))
+}
+
+fn determine_output_path(path_buf: Option<PathBuf>, path: &Path) -> Result<PathBuf, anyhow::Error> {
     let output_path = match path_buf {
         Some(path) => path,
+        None if path == Path::new(STDIN) => {
+            return Err(anyhow::anyhow!(
+                "Reading the statement from stdin; specify the orders file with --output"
+            ))
+        }
         None => {
             let input_stem = path
                 .file_stem()
                 .ok_or_else(|| anyhow::anyhow!("Cannot determine filename from input path"))?
                 .to_str()
                 .ok_or_else(|| anyhow::anyhow!("Input filename contains invalid UTF-8"))?;
-            let mut output_path = path.clone();
+            let mut output_path = path.to_path_buf();
             output_path.set_file_name(format!("{}-orders.csv", input_stem));
             output_path
         }
@@ -80,15 +510,21 @@ fn determine_output_path(
     Ok(output_path)
 }
 
-fn determine_config_path(input_path: &PathBuf, config_arg: Option<PathBuf>) -> Result<PathBuf> {
+/// The `--config` path, or the `config.toml` next to the input. Relative
+/// input paths are resolved against `current_dir`.
+fn determine_config_path(
+    input_path: &Path,
+    config_arg: Option<PathBuf>,
+    current_dir: &Path,
+) -> Result<PathBuf> {
     match config_arg {
         Some(path) => Ok(path),
         None => {
-            let mut default_config = input_path
-                .parent()
-                .ok_or_else(|| anyhow::anyhow!("Cannot determine parent directory of input file"))?
-                .to_path_buf();
-            default_config.push("config.toml");
+            // For a statement on stdin the parent is empty, i.e. `current_dir`
+            let parent = input_path.parent().ok_or_else(|| {
+                anyhow::anyhow!("Cannot determine parent directory of input file")
+            })?;
+            let default_config = current_dir.join(parent).join("config.toml");
             if !default_config.exists() {
                 return Err(anyhow::anyhow!(
                     "Config file not found at {:?}. Please create it or specify with --config",
@@ -103,59 +539,122 @@ fn determine_config_path(input_path: &PathBuf, config_arg: Option<PathBuf>) -> R
 fn load_config(path: &PathBuf) -> Result<Config> {
     let content = fs::read_to_string(path)?;
     let config: Config = toml::from_str(&content)?;
+    allocation::classes(&config)?;
     Ok(config)
 }
 
+/// Converts base-currency orders into the currency each fund trades in.
+fn orders_in_trading_currency(
+    orders: Vec<Order>,
+    currency: &CurrencyConfig,
+    holdings: &[Holding],
+    fx: &FxRates,
+) -> Result<Vec<Order>> {
+    orders
+        .into_iter()
+        .map(|order| {
+            let trading_currency = currency.trading_currency(&order.fund, holdings);
+            Ok(Order {
+                amount: fx.to_currency(order.amount, trading_currency)?,
+                currency: trading_currency.to_string(),
+                ..order
+            })
+        })
+        .collect()
+}
 
-
-fn calculate_orders(config: &Config, cash: Decimal, holdings: &[Holding]) -> Result<Vec<Order>> {
-    // Calculate total portfolio value
-    let total_invested: Decimal = holdings.iter().map(|h| h.market_value).sum();
-    let total_value = total_invested + cash;
-
-    let mut orders = Vec::new();
-
-    for (fund_name, target_percent) in &config.funds {
-        let target_value = total_value * (*target_percent / Decimal::from(100));
-        let current_value = holdings
-            .iter()
-            .find(|h| &h.symbol == fund_name)
-            .map(|h| h.market_value)
-            .unwrap_or(Decimal::ZERO);
-
-        let difference = target_value - current_value;
-
-        if difference.abs() > Decimal::ONE {
-            // Only create orders for differences > $1
-            let action = if difference > Decimal::ZERO {
-                "BUY"
-            } else {
-                "SELL"
-            };
-            orders.push(Order {
-                fund: fund_name.clone(),
-                action: action.to_string(),
-                amount: difference.abs(),
-            });
+fn write_orders(
+    path: &PathBuf,
+    sources: &[(Input, Statement)],
+    summary: &[Vec<String>],
+    orders: &[Order],
+) -> Result<()> {
+    let mut writer = csv::WriterBuilder::new().flexible(true).from_path(path)?;
+
+    // Write each statement the orders were generated from, in the same
+    // summary-rows-then-table layout as the bank export
+    for (input, statement) in sources {
+        let source = &input.path;
+        let source_name = if source == Path::new(STDIN) {
+            "stdin".into()
+        } else {
+            source
+                .file_name()
+                .unwrap_or(source.as_os_str())
+                .to_string_lossy()
+        };
+        writer.write_record(["Statement", &source_name])?;
+        if let Some(account) = &input.account {
+            writer.write_record(["Account ID", account])?;
+        }
+        if let Some(kind) = &input.kind {
+            writer.write_record(["Account Type", kind])?;
+        }
+        if let Some(as_of) = statement.as_of {
+            writer.write_record(["As of Date", &as_of.to_string()])?;
         }
+        if let Some(account) = &statement.account {
+            writer.write_record(["Account", account])?;
+        }
+        writer.write_record(["", ""])?;
     }
 
-    Ok(orders)
-}
-
-fn write_orders(path: &PathBuf, orders: &[Order]) -> Result<()> {
-    let mut writer = csv::Writer::from_path(path)?;
+    // Cash flows and the cash reserve apply to the whole plan, so they
+    // follow the statements
+    for row in summary {
+        writer.write_record(row)?;
+    }
+    if !summary.is_empty() {
+        writer.write_record(["", ""])?;
+    }
 
-    // Write header
-    writer.write_record(&["Symbol", "Action", "Amount"])?;
+    // Write header; a household's orders also say which account places them
+    let household = sources.len() > 1;
+    let mut header = vec![
+        "Symbol",
+        "Action",
+        "Amount",
+        "Currency",
+        "Quantity",
+        "Phase",
+        "Trade Date",
+        "Settlement Date",
+    ];
+    if household {
+        header.push("Account");
+    }
+    writer.write_record(&header)?;
 
     // Write orders
     for order in orders {
-        writer.write_record(&[
+        // Amounts are already rounded to the cent by `round_to_cents`
+        let amount = format!("{:.2}", order.amount);
+        let quantity = order
+            .quantity
+            .map(|q| q.normalize().to_string())
+            .unwrap_or_default();
+        let (phase, trade_date, settlement_date) = match order.schedule {
+            Some(schedule) => (
+                schedule.phase.to_string(),
+                schedule.trade_date.to_string(),
+                schedule.settlement_date.to_string(),
+            ),
+            None => Default::default(),
+        };
+        let mut record = vec![
             &order.fund,
             &order.action,
-            &order.amount.round_dp(2).to_string(),
-        ])?;
+            &amount,
+            &order.currency,
+            &quantity,
+            &phase,
+            &trade_date,
+            &settlement_date,
+        ];
+        if household {
+            record.push(&order.account);
+        }
+        writer.write_record(&record)?;
     }
 
     writer.flush()?;
@@ -165,8 +664,8 @@ fn write_orders(path: &PathBuf, orders: &[Order]) -> Result<()> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use settlement::Schedule;
     use std::io::Write;
-    use std::str::FromStr;
     use tempfile::NamedTempFile;
 
     #[test]
@@ -188,158 +687,191 @@ ABC789 = "10.0"
         assert_eq!(config.funds.get("ABC789"), Some(&Decimal::from(10)));
     }
 
-
-
-    #[test]
-    fn test_calculate_orders() {
-        let mut funds = HashMap::new();
-        funds.insert("ABC123".to_string(), Decimal::from(60));
-        funds.insert("ABC456".to_string(), Decimal::from(30));
-        funds.insert("ABC789".to_string(), Decimal::from(10));
-        let config = Config { funds };
-
-        let holdings = vec![
-            Holding {
-                symbol: "ABC123".to_string(),
-                market_value: Decimal::from(6000),
-            },
-            Holding {
-                symbol: "ABC456".to_string(),
-                market_value: Decimal::from(2000),
-            },
-            Holding {
-                symbol: "ABC789".to_string(),
-                market_value: Decimal::from(1000),
-            },
-        ];
-        let cash = Decimal::from(1000);
-
-        let orders = calculate_orders(&config, cash, &holdings).unwrap();
-
-        // Total value: 6000 + 2000 + 1000 + 1000 = 10000
-        // Target ABC123: 10000 * 0.6 = 6000 (current: 6000, diff: 0)
-        // Target ABC456: 10000 * 0.3 = 3000 (current: 2000, diff: +1000)
-        // Target ABC789: 10000 * 0.1 = 1000 (current: 1000, diff: 0)
-
-        assert_eq!(orders.len(), 1);
-        assert_eq!(orders[0].fund, "ABC456");
-        assert_eq!(orders[0].action, "BUY");
-        assert_eq!(orders[0].amount, Decimal::from(1000));
-    }
-
-    #[test]
-    fn test_calculate_orders_with_sells() {
-        let mut funds = HashMap::new();
-        funds.insert("ABC123".to_string(), Decimal::from(30));
-        funds.insert("ABC456".to_string(), Decimal::from(30));
-        funds.insert("ABC789".to_string(), Decimal::from(40));
-        let config = Config { funds };
-
-        let holdings = vec![
-            Holding {
-                symbol: "ABC123".to_string(),
-                market_value: Decimal::from(6000),
-            },
-            Holding {
-                symbol: "ABC456".to_string(),
-                market_value: Decimal::from(2000),
-            },
-            Holding {
-                symbol: "ABC789".to_string(),
-                market_value: Decimal::from(1000),
-            },
-        ];
-        let cash = Decimal::from(1000);
-
-        let orders = calculate_orders(&config, cash, &holdings).unwrap();
-
-        // Total value: 10000
-        // Target ABC123: 10000 * 0.3 = 3000 (current: 6000, diff: -3000)
-        // Target ABC456: 10000 * 0.3 = 3000 (current: 2000, diff: +1000)
-        // Target ABC789: 10000 * 0.4 = 4000 (current: 1000, diff: +3000)
-
-        assert_eq!(orders.len(), 3);
-
-        let abc123_order = orders.iter().find(|o| o.fund == "ABC123").unwrap();
-        assert_eq!(abc123_order.action, "SELL");
-        assert_eq!(abc123_order.amount, Decimal::from(3000));
-
-        let abc456_order = orders.iter().find(|o| o.fund == "ABC456").unwrap();
-        assert_eq!(abc456_order.action, "BUY");
-        assert_eq!(abc456_order.amount, Decimal::from(1000));
-
-        let abc789_order = orders.iter().find(|o| o.fund == "ABC789").unwrap();
-        assert_eq!(abc789_order.action, "BUY");
-        assert_eq!(abc789_order.amount, Decimal::from(3000));
-    }
-
     #[test]
-    fn test_calculate_orders_ignores_small_differences() {
-        let mut funds = HashMap::new();
-        funds.insert("ABC123".to_string(), Decimal::from(60));
-        let config = Config { funds };
-
-        let holdings = vec![Holding {
-            symbol: "ABC123".to_string(),
-            market_value: Decimal::from_str("6000.50").unwrap(),
-        }];
-        let cash = Decimal::ZERO;
-
-        let orders = calculate_orders(&config, cash, &holdings).unwrap();
-
-        // Total value: 6000.50
-        // Target ABC123: 6000.50 * 0.6 = 3600.30 (current: 6000.50, diff: -2400.20)
-        // Should create order since difference > $1
-        assert_eq!(orders.len(), 1);
-
-        // Test with small difference
-        let holdings_small_diff = vec![Holding {
-            symbol: "ABC123".to_string(),
-            market_value: Decimal::from_str("5999.50").unwrap(),
-        }];
-        let cash_small = Decimal::from_str("0.50").unwrap();
+    fn test_load_config_money_formats() {
+        let config_content = r#"
+[funds]
+ABC123 = "100.0"
 
-        let orders_small = calculate_orders(&config, cash_small, &holdings_small_diff).unwrap();
-        // Total: 6000, Target: 3600, Current: 5999.50, diff: -2399.50 > $1
-        assert_eq!(orders_small.len(), 1);
+[formats.bank-csv]
+decimal_separator = ","
+thousands_separator = " "
+"#;
+        let mut temp_file = NamedTempFile::new().unwrap();
+        write!(temp_file, "{}", config_content).unwrap();
 
-        // Test with very small difference
-        let holdings_tiny_diff = vec![Holding {
-            symbol: "ABC123".to_string(),
-            market_value: Decimal::from_str("5999.99").unwrap(),
-        }];
-        let cash_tiny = Decimal::from_str("0.01").unwrap();
+        let config = load_config(&temp_file.path().to_path_buf()).unwrap();
 
-        let orders_tiny = calculate_orders(&config, cash_tiny, &holdings_tiny_diff).unwrap();
-        // Total: 6000, Target: 3600, Current: 5999.99, diff: -2399.99 > $1
-        assert_eq!(orders_tiny.len(), 1);
+        assert_eq!(
+            config.formats.get(&StatementFormat::BankCsv),
+            Some(&MoneyFormat {
+                decimal_separator: ',',
+                thousands_separator: Some(' '),
+            })
+        );
+        assert_eq!(config.formats.get(&StatementFormat::PositionsCsv), None);
     }
 
     #[test]
     fn test_write_orders() {
         let orders = vec![
             Order {
+                account: "holdings".to_string(),
                 fund: "ABC123".to_string(),
                 action: "BUY".to_string(),
                 amount: Decimal::from_str("1500.50").unwrap(),
+                currency: "CAD".to_string(),
+                quantity: Some(Decimal::from_str("30.01").unwrap()),
+                schedule: Some(Schedule {
+                    phase: 2,
+                    trade_date: parse_trade_date("2025-05-28").unwrap(),
+                    settlement_date: parse_trade_date("2025-05-30").unwrap(),
+                }),
             },
             Order {
+                account: "holdings".to_string(),
                 fund: "ABC456".to_string(),
                 action: "SELL".to_string(),
                 amount: Decimal::from_str("750.25").unwrap(),
+                currency: "USD".to_string(),
+                quantity: None,
+                schedule: None,
             },
         ];
 
+        let statement = Statement {
+            as_of: statement::parse_timestamp("2025-05-24 14:39:25"),
+            account: Some("BANK NAME - ACCOUNT NUMBER".to_string()),
+            ..Default::default()
+        };
+        let input = parse_input("/statements/holdings.csv").unwrap();
+
         let temp_file = NamedTempFile::new().unwrap();
-        write_orders(&temp_file.path().to_path_buf(), &orders).unwrap();
+        write_orders(
+            &temp_file.path().to_path_buf(),
+            &[(input, statement)],
+            &[],
+            &orders,
+        )
+        .unwrap();
 
         let content = std::fs::read_to_string(temp_file.path()).unwrap();
         let lines: Vec<&str> = content.lines().collect();
 
-        assert_eq!(lines.len(), 3); // header + 2 orders
-        assert_eq!(lines[0], "Symbol,Action,Amount");
-        assert_eq!(lines[1], "ABC123,BUY,1500.50");
-        assert_eq!(lines[2], "ABC456,SELL,750.25");
+        assert_eq!(lines.len(), 7); // statement summary + header + 2 orders
+        assert_eq!(lines[0], "Statement,holdings.csv");
+        assert_eq!(lines[1], "As of Date,2025-05-24 14:39:25");
+        assert_eq!(lines[2], "Account,BANK NAME - ACCOUNT NUMBER");
+        assert_eq!(lines[3], ",");
+        assert_eq!(
+            lines[4],
+            "Symbol,Action,Amount,Currency,Quantity,Phase,Trade Date,Settlement Date"
+        );
+        assert_eq!(
+            lines[5],
+            "ABC123,BUY,1500.50,CAD,30.01,2,2025-05-28,2025-05-30"
+        );
+        assert_eq!(lines[6], "ABC456,SELL,750.25,USD,,,,");
+    }
+
+    #[test]
+    fn test_write_household_orders() {
+        let orders = vec![Order {
+            account: "tfsa".to_string(),
+            fund: "ABC123".to_string(),
+            action: "BUY".to_string(),
+            amount: Decimal::from_str("100").unwrap(),
+            currency: "CAD".to_string(),
+            quantity: Some(Decimal::from(2)),
+            schedule: None,
+        }];
+        let sources = [
+            (
+                parse_input("rrsp:rrsp=rrsp.csv").unwrap(),
+                Statement::default(),
+            ),
+            (parse_input("tfsa=tfsa.csv").unwrap(), Statement::default()),
+        ];
+
+        let temp_file = NamedTempFile::new().unwrap();
+        let summary = [
+            vec!["Contribution", "5000.00", "CAD", "tfsa"],
+            vec!["Cash Reserve", "250.00", "CAD"],
+        ]
+        .map(|row| row.into_iter().map(String::from).collect::<Vec<_>>());
+        write_orders(&temp_file.path().to_path_buf(), &sources, &summary, &orders).unwrap();
+
+        let content = std::fs::read_to_string(temp_file.path()).unwrap();
+        assert_eq!(
+            content,
+            "Statement,rrsp.csv\nAccount ID,rrsp\nAccount Type,rrsp\n,\n\
+             Statement,tfsa.csv\nAccount ID,tfsa\n,\n\
+             Contribution,5000.00,CAD,tfsa\nCash Reserve,250.00,CAD\n,\n\
+             Symbol,Action,Amount,Currency,Quantity,Phase,Trade Date,Settlement Date,Account\n\
+             ABC123,BUY,100.00,CAD,2,,,,tfsa\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_trade_date() {
+        assert_eq!(
+            parse_trade_date("2025-05-26").unwrap(),
+            NaiveDate::from_ymd_opt(2025, 5, 26).unwrap()
+        );
+        assert!(parse_trade_date("2025-05-26 10:00:00").is_err());
+        assert!(parse_trade_date("26/05/2025").is_err());
+    }
+
+    #[test]
+    fn test_parse_cash_flow() {
+        assert_eq!(
+            parse_cash_flow("5000").unwrap(),
+            CashFlow {
+                account: None,
+                amount: Decimal::from(5000),
+            }
+        );
+        assert_eq!(
+            parse_cash_flow("rrsp=1234.56").unwrap(),
+            CashFlow {
+                account: Some("rrsp".to_string()),
+                amount: Decimal::from_str("1234.56").unwrap(),
+            }
+        );
+        assert!(parse_cash_flow("$5,000").is_err());
+        assert!(parse_cash_flow("-100").is_err());
+        assert!(parse_cash_flow("rrsp=0").is_err());
+    }
+
+    #[test]
+    fn test_parse_input() {
+        assert_eq!(
+            parse_input("statements/rrsp.csv").unwrap(),
+            Input {
+                account: None,
+                kind: None,
+                path: PathBuf::from("statements/rrsp.csv"),
+            }
+        );
+        assert_eq!(
+            parse_input("rrsp:RRSP=statements/rrsp.csv").unwrap(),
+            Input {
+                account: Some("rrsp".to_string()),
+                kind: Some("rrsp".to_string()),
+                path: PathBuf::from("statements/rrsp.csv"),
+            }
+        );
+        let joint = parse_input("joint=-").unwrap();
+        assert_eq!(joint.account_id(), "joint");
+        assert_eq!(joint.path, PathBuf::from(STDIN));
+
+        // Paths that only look like tags are kept whole
+        assert_eq!(
+            parse_input("C:/statements/a=b.csv").unwrap().path,
+            PathBuf::from("C:/statements/a=b.csv")
+        );
+        assert_eq!(parse_input("x/rrsp.csv").unwrap().account_id(), "rrsp");
+        assert!(parse_input("rrsp=").is_err());
     }
 
     #[test]
@@ -383,7 +915,8 @@ ABC789 = "10.0"
         // Test with provided config path
         let input_path = PathBuf::from("test/input.csv");
         let config_path = PathBuf::from("custom/config.toml");
-        let result = determine_config_path(&input_path, Some(config_path.clone())).unwrap();
+        let result =
+            determine_config_path(&input_path, Some(config_path.clone()), Path::new("")).unwrap();
         assert_eq!(result, config_path);
 
         // Test with default config path (file exists)
@@ -395,7 +928,7 @@ ABC789 = "10.0"
         std::fs::write(&input_file, "test content").unwrap();
         std::fs::write(&config_file, "[funds]\nTEST = \"100.0\"").unwrap();
 
-        let result = determine_config_path(&input_file, None).unwrap();
+        let result = determine_config_path(&input_file, None, Path::new("")).unwrap();
         assert_eq!(result, config_file);
 
         // Test with default config path (file doesn't exist)
@@ -404,19 +937,24 @@ ABC789 = "10.0"
         std::fs::write(&input_file2, "test content").unwrap();
         // Don't create config.toml
 
-        let result = determine_config_path(&input_file2, None);
+        let result = determine_config_path(&input_file2, None, Path::new(""));
         assert!(result.is_err());
         assert!(result
             .unwrap_err()
             .to_string()
             .contains("Config file not found"));
 
+        // A statement on stdin uses the config.toml in the current directory
+        let result = determine_config_path(Path::new(STDIN), None, temp_dir.path()).unwrap();
+        assert_eq!(result, config_file);
+        assert!(determine_config_path(Path::new(STDIN), None, temp_dir2.path()).is_err());
+
         // Test with isolated directory that doesn't contain config.toml
         let temp_dir3 = tempfile::TempDir::new().unwrap();
         let isolated_input = temp_dir3.path().join("input.csv");
         std::fs::write(&isolated_input, "test content").unwrap();
 
-        let result2 = determine_config_path(&isolated_input, None);
+        let result2 = determine_config_path(&isolated_input, None, Path::new(""));
         assert!(result2.is_err());
         assert!(result2
             .unwrap_err()
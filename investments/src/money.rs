@@ -0,0 +1,203 @@
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::str::FromStr;
+
+const CURRENCY_SYMBOLS: &[char] = &['$', '€', '£', '¥'];
+
+/// How amounts are written in a statement format.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(default)]
+pub struct MoneyFormat {
+    pub decimal_separator: char,
+    /// Digit grouping character, removed before parsing. Whitespace
+    /// (including non-breaking spaces) is always treated as grouping, and
+    /// groups after the first must have three digits.
+    pub thousands_separator: Option<char>,
+}
+
+impl Default for MoneyFormat {
+    fn default() -> Self {
+        MoneyFormat {
+            decimal_separator: '.',
+            thousands_separator: Some(','),
+        }
+    }
+}
+
+/// Parses a statement amount such as `$1,234.56`, `(123.45)`, `1 234,56` or
+/// `1234.56 CAD` into a `Decimal`. Returns `None` if `text` is not an amount.
+pub fn parse_money(text: &str, format: &MoneyFormat) -> Option<Decimal> {
+    parse_money_with_currency(text, format).map(|(amount, _)| amount)
+}
+
+/// Like `parse_money`, but also returns the currency code written with the
+/// amount, if any.
+pub fn parse_money_with_currency(
+    text: &str,
+    format: &MoneyFormat,
+) -> Option<(Decimal, Option<String>)> {
+    let mut negative = false;
+
+    // Currency codes, outside or inside accounting parentheses:
+    // 1234.56 CAD, USD (5.00), (5.00 USD)
+    let (mut text, mut currency) = split_currency_code(text.trim());
+    if let Some(inner) = text.strip_prefix('(').and_then(|t| t.strip_suffix(')')) {
+        negative = true;
+        text = inner.trim();
+    }
+    if currency.is_none() {
+        (text, currency) = split_currency_code(text);
+    }
+
+    // Signs and currency symbols in either order: -$5, $-5, C$5, 5-
+    loop {
+        let before = text;
+        if let Some(rest) = text.strip_prefix('-').or_else(|| text.strip_suffix('-')) {
+            negative = !negative;
+            text = rest;
+        }
+        text = text.strip_prefix('+').unwrap_or(text);
+        text = strip_currency_symbol(text).trim();
+        if text == before {
+            break;
+        }
+    }
+
+    // Grouping is only accepted between groups of three digits, so that an
+    // amount in another format, such as 12,34 or 1 234,56, is not misread
+    let (whole, fraction) = match text.split_once(format.decimal_separator) {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (text, None),
+    };
+    let groups: Vec<&str> = whole
+        .split(|c: char| c.is_whitespace() || Some(c) == format.thousands_separator)
+        .collect();
+    if groups.len() > 1
+        && (groups[0].is_empty()
+            || groups[0].len() > 3
+            || groups[1..].iter().any(|group| group.len() != 3))
+    {
+        return None;
+    }
+    let mut normalized = groups.concat();
+    if let Some(fraction) = fraction {
+        normalized.push('.');
+        normalized.push_str(fraction);
+    }
+    if !normalized.chars().all(|c| c.is_ascii_digit() || c == '.') {
+        return None;
+    }
+    if !normalized.chars().any(|c| c.is_ascii_digit()) {
+        return None;
+    }
+
+    let value = Decimal::from_str(&normalized).ok()?;
+    let value = if negative { -value } else { value };
+    Some((value, currency.map(str::to_string)))
+}
+
+/// Splits a three-letter currency code off either end of `text`.
+fn split_currency_code(text: &str) -> (&str, Option<&str>) {
+    let is_code = |code: &str| code.len() == 3 && code.bytes().all(|b| b.is_ascii_uppercase());
+    if let Some((amount, code)) = text.rsplit_once(char::is_whitespace) {
+        if is_code(code) {
+            return (amount.trim_end(), Some(code));
+        }
+    }
+    if let Some((code, amount)) = text.split_once(char::is_whitespace) {
+        if is_code(code) {
+            return (amount.trim_start(), Some(code));
+        }
+    }
+    (text, None)
+}
+
+/// Removes a currency symbol, with an optional country prefix such as `C$` or
+/// `US$`, from either end of `text`.
+fn strip_currency_symbol(text: &str) -> &str {
+    if let Some(i) = text.find(CURRENCY_SYMBOLS) {
+        let prefix = &text[..i];
+        if prefix.len() <= 2 && prefix.bytes().all(|b| b.is_ascii_uppercase()) {
+            let symbol_len = text[i..].chars().next().map_or(0, char::len_utf8);
+            return &text[i + symbol_len..];
+        }
+    }
+    text.strip_suffix(CURRENCY_SYMBOLS).unwrap_or(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Option<Decimal> {
+        parse_money(text, &MoneyFormat::default())
+    }
+
+    fn dec(text: &str) -> Option<Decimal> {
+        Some(Decimal::from_str(text).unwrap())
+    }
+
+    #[test]
+    fn test_parse_money_default_format() {
+        assert_eq!(parse("1234.56"), dec("1234.56"));
+        assert_eq!(parse("$1,234.56"), dec("1234.56"));
+        assert_eq!(parse("C$1,234.56"), dec("1234.56"));
+        assert_eq!(parse("(123.45)"), dec("-123.45"));
+        assert_eq!(parse("($123.45)"), dec("-123.45"));
+        assert_eq!(parse("-$5.00"), dec("-5.00"));
+        assert_eq!(parse("$-5.00"), dec("-5.00"));
+        assert_eq!(parse("1234.56 CAD"), dec("1234.56"));
+        assert_eq!(parse("USD 1,234.56"), dec("1234.56"));
+        assert_eq!(parse("-23,321.83 USD"), dec("-23321.83"));
+        assert_eq!(parse("(5.00 USD)"), dec("-5.00"));
+    }
+
+    #[test]
+    fn test_parse_money_with_currency() {
+        let format = MoneyFormat::default();
+        assert_eq!(
+            parse_money_with_currency("1,234.56 USD", &format),
+            Some((
+                Decimal::from_str("1234.56").unwrap(),
+                Some("USD".to_string())
+            ))
+        );
+        assert_eq!(
+            parse_money_with_currency("CAD (5.00)", &format),
+            Some((Decimal::from_str("-5.00").unwrap(), Some("CAD".to_string())))
+        );
+        assert_eq!(
+            parse_money_with_currency("$5.00", &format),
+            Some((Decimal::from_str("5.00").unwrap(), None))
+        );
+    }
+
+    #[test]
+    fn test_parse_money_european_format() {
+        let format = MoneyFormat {
+            decimal_separator: ',',
+            thousands_separator: Some('.'),
+        };
+        assert_eq!(parse_money("1 234,56", &format), dec("1234.56"));
+        assert_eq!(parse_money("1\u{a0}234,56 €", &format), dec("1234.56"));
+        assert_eq!(parse_money("1.234,56", &format), dec("1234.56"));
+        assert_eq!(parse_money("(1.234,56)", &format), dec("-1234.56"));
+    }
+
+    #[test]
+    fn test_parse_money_rejects_non_amounts() {
+        assert_eq!(parse(""), None);
+        assert_eq!(parse("N/A"), None);
+        assert_eq!(parse("#VALUE!"), None);
+        assert_eq!(parse("$"), None);
+        assert_eq!(parse("12abc"), None);
+        assert_eq!(parse("about 100"), None);
+        // Grouping that is not in threes is another format, not thousands
+        assert_eq!(parse("12,34"), None);
+        assert_eq!(parse("1 234,56"), None);
+        assert_eq!(parse("1,2345.00"), None);
+        assert_eq!(parse(",123"), None);
+        assert_eq!(parse("1.234,56"), None);
+        assert_eq!(parse("12,345,678.9"), dec("12345678.9"));
+    }
+}
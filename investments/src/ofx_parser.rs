@@ -0,0 +1,566 @@
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use rust_decimal::Decimal;
+use std::collections::HashMap;
+
+use crate::diagnostics::{ParseError, ParseWarning};
+use crate::money::{parse_money, MoneyFormat};
+use crate::statement::{Holding, Statement, StatementParser};
+
+/// Position aggregates that can appear in an `INVPOSLIST`.
+const POSITION_TYPES: &[&str] = &["POSMF", "POSSTOCK", "POSDEBT", "POSOPT", "POSOTHER"];
+
+/// One element of an OFX document. Leaves carry `text`; aggregates carry
+/// `children`.
+#[derive(Debug, Default)]
+struct Element {
+    name: String,
+    line: u64,
+    text: Option<String>,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    /// Follows `path` down through child elements.
+    fn at(&self, path: &[&str]) -> Option<&Element> {
+        path.iter()
+            .try_fold(self, |element, name| element.child(name))
+    }
+
+    /// The non-blank text of the leaf at `path`.
+    fn text_at(&self, path: &[&str]) -> Option<&str> {
+        self.at(path)?
+            .text
+            .as_deref()
+            .filter(|text| !text.is_empty())
+    }
+
+    /// Every descendant named `name`, in document order. Matches are not
+    /// searched further.
+    fn find_all<'a>(&'a self, name: &str, found: &mut Vec<&'a Element>) {
+        for child in &self.children {
+            if child.name == name {
+                found.push(child);
+            } else {
+                child.find_all(name, found);
+            }
+        }
+    }
+}
+
+enum Token<'a> {
+    Open(&'a str),
+    Close(&'a str),
+    Text(&'a str),
+}
+
+/// Splits OFX markup into tags and text, with the line each starts on.
+/// Processing instructions, comments and declarations are dropped.
+fn tokenize(text: &str, first_line: u64) -> Result<Vec<(u64, Token<'_>)>, ParseError> {
+    let mut tokens = Vec::new();
+    let mut line = first_line;
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix('<') {
+            let terminator = if after.starts_with("!--") { "-->" } else { ">" };
+            let end = after
+                .find(terminator)
+                .ok_or_else(|| ParseError::Malformed {
+                    line: Some(line),
+                    message: "unterminated tag".to_string(),
+                })?;
+            let tag = after[..end].trim();
+            if let Some(name) = tag.strip_prefix('/') {
+                tokens.push((line, Token::Close(name.trim())));
+            } else if let Some(name) = tag.strip_suffix('/') {
+                tokens.push((line, Token::Open(name.trim())));
+                tokens.push((line, Token::Close(name.trim())));
+            } else if !tag.starts_with(['?', '!']) {
+                tokens.push((line, Token::Open(tag)));
+            }
+            line += after[..end].matches('\n').count() as u64;
+            rest = &after[end + terminator.len()..];
+        } else {
+            let end = rest.find('<').unwrap_or(rest.len());
+            let text = &rest[..end];
+            let trimmed = text.trim();
+            if !trimmed.is_empty() {
+                let leading = &text[..text.find(trimmed).unwrap_or(0)];
+                let text_line = line + leading.matches('\n').count() as u64;
+                tokens.push((text_line, Token::Text(trimmed)));
+            }
+            line += text.matches('\n').count() as u64;
+            rest = &rest[end..];
+        }
+    }
+    Ok(tokens)
+}
+
+/// Replaces the character entities allowed in OFX text.
+fn decode_entities(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&nbsp;", "\u{a0}")
+        .replace("&amp;", "&")
+}
+
+/// Builds the element tree for OFX markup. Both OFX 2 (XML) and OFX 1 (SGML)
+/// are accepted: in SGML, leaf elements are usually not closed, so a leaf
+/// ends at the next tag.
+fn parse_elements(text: &str, first_line: u64) -> Result<Element, ParseError> {
+    fn close_top(stack: &mut Vec<Element>) {
+        let element = stack.pop().expect("the root is never closed");
+        stack
+            .last_mut()
+            .expect("the root is never closed")
+            .children
+            .push(element);
+    }
+    let is_open_leaf = |stack: &[Element]| stack.len() > 1 && stack[stack.len() - 1].text.is_some();
+
+    let mut stack = vec![Element::default()];
+    for (line, token) in tokenize(text, first_line)? {
+        match token {
+            Token::Open(name) => {
+                if is_open_leaf(&stack) {
+                    close_top(&mut stack);
+                }
+                stack.push(Element {
+                    name: name.to_ascii_uppercase(),
+                    line,
+                    ..Default::default()
+                });
+            }
+            Token::Text(text) => {
+                let top = stack.last_mut().expect("the root is never closed");
+                if top.text.is_none() && top.children.is_empty() {
+                    top.text = Some(decode_entities(text));
+                }
+            }
+            Token::Close(name) => {
+                let name = name.to_ascii_uppercase();
+                let Some(index) = stack.iter().skip(1).rposition(|e| e.name == name) else {
+                    return Err(ParseError::Malformed {
+                        line: Some(line),
+                        message: format!("closing tag </{}> has no matching <{}>", name, name),
+                    });
+                };
+                while stack.len() > index + 1 {
+                    close_top(&mut stack);
+                }
+            }
+        }
+    }
+    while stack.len() > 1 {
+        close_top(&mut stack);
+    }
+    Ok(stack.pop().expect("the root is never closed"))
+}
+
+/// Parses an OFX date such as `20250524143925.000[-5:EST]`. The time zone is
+/// ignored, like the local times in the other statement formats.
+fn parse_ofx_date(text: &str) -> Option<NaiveDateTime> {
+    let digits = text
+        .find(|c: char| !c.is_ascii_digit())
+        .map_or(text, |end| &text[..end]);
+    match digits.len() {
+        14.. => NaiveDateTime::parse_from_str(&digits[..14], "%Y%m%d%H%M%S").ok(),
+        12.. => NaiveDateTime::parse_from_str(&digits[..12], "%Y%m%d%H%M").ok(),
+        8.. => NaiveDate::parse_from_str(&digits[..8], "%Y%m%d")
+            .ok()
+            .map(|date| date.and_time(NaiveTime::MIN)),
+        _ => None,
+    }
+}
+
+/// Reads typed values out of elements, recording a warning for anything that
+/// cannot be read.
+struct ValueReader<'a> {
+    money: &'a MoneyFormat,
+    warnings: &'a mut Vec<ParseWarning>,
+}
+
+impl ValueReader<'_> {
+    fn invalid(&mut self, element: &Element, text: &str) {
+        self.warnings.push(ParseWarning::InvalidValue {
+            line: element.line,
+            column: element.name.clone(),
+            text: text.to_string(),
+        });
+    }
+
+    /// Parses the amount at `path` under `parent`, recording a warning if it
+    /// is missing or unreadable.
+    fn decimal(&mut self, parent: &Element, path: &[&str]) -> Option<Decimal> {
+        if parent.text_at(path).is_none() {
+            self.warnings.push(ParseWarning::MissingValue {
+                line: parent.line,
+                column: path.last().copied().unwrap_or_default().to_string(),
+            });
+            return None;
+        }
+        self.optional_decimal(parent, path)
+    }
+
+    /// Parses an optional amount. Absent elements are `None`; unreadable ones
+    /// are `None` with a warning.
+    fn optional_decimal(&mut self, parent: &Element, path: &[&str]) -> Option<Decimal> {
+        let element = parent.at(path)?;
+        let text = element.text.as_deref().filter(|text| !text.is_empty())?;
+        let value = parse_money(text, self.money);
+        if value.is_none() {
+            self.invalid(element, text);
+        }
+        value
+    }
+
+    fn date(&mut self, parent: &Element, path: &[&str]) -> Option<NaiveDateTime> {
+        let element = parent.at(path)?;
+        let text = element.text.as_deref().unwrap_or_default();
+        let date = parse_ofx_date(text);
+        if date.is_none() {
+            self.invalid(element, text);
+        }
+        date
+    }
+}
+
+/// Ticker and name of a security from the `SECLIST`.
+struct Security<'a> {
+    ticker: Option<&'a str>,
+    name: Option<&'a str>,
+}
+
+/// Maps each `UNIQUEID` in the document's security list to its ticker and name.
+fn securities(root: &Element) -> HashMap<&str, Security<'_>> {
+    let mut infos = Vec::new();
+    root.find_all("SECINFO", &mut infos);
+    infos
+        .into_iter()
+        .filter_map(|info| {
+            let id = info.text_at(&["SECID", "UNIQUEID"])?;
+            let security = Security {
+                ticker: info.text_at(&["TICKER"]),
+                name: info.text_at(&["SECNAME"]),
+            };
+            Some((id, security))
+        })
+        .collect()
+}
+
+/// An OFX or QFX investment statement download (`INVSTMTRS`), in either the
+/// SGML (OFX 1) or XML (OFX 2) encoding. Positions come from `INVPOSLIST`,
+/// cash from `INVBAL`, and tickers and names from the `SECLIST`.
+pub struct OfxParser;
+
+impl StatementParser for OfxParser {
+    fn detect(&self, data: &[u8]) -> bool {
+        let head = String::from_utf8_lossy(&data[..data.len().min(4096)]).to_ascii_uppercase();
+        head.trim_start_matches('\u{feff}')
+            .trim_start()
+            .starts_with("OFXHEADER")
+            || head.contains("<OFX>")
+    }
+
+    fn parse(
+        &self,
+        data: &[u8],
+        money: &MoneyFormat,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<Statement, ParseError> {
+        // OFX 1 files are often Windows-1252; amounts and tags are ASCII either way
+        let text = String::from_utf8_lossy(data);
+        let start = text
+            .as_bytes()
+            .windows(5)
+            .position(|window| window.eq_ignore_ascii_case(b"<OFX>"))
+            .ok_or_else(|| ParseError::MissingElement {
+                element: "OFX".to_string(),
+            })?;
+        let first_line = 1 + text[..start].matches('\n').count() as u64;
+        let root = parse_elements(&text[start..], first_line)?;
+
+        let mut responses = Vec::new();
+        root.find_all("INVSTMTRS", &mut responses);
+        let response = match responses[..] {
+            [response] => response,
+            [] => {
+                return Err(ParseError::MissingElement {
+                    element: "INVSTMTRS".to_string(),
+                })
+            }
+            [_, second, ..] => {
+                return Err(ParseError::Malformed {
+                    line: Some(second.line),
+                    message: format!(
+                        "file has {} investment statements; download one account per file",
+                        responses.len()
+                    ),
+                })
+            }
+        };
+
+        let mut reader = ValueReader { money, warnings };
+        let securities = securities(&root);
+        let default_currency = response.text_at(&["CURDEF"]).map(str::to_uppercase);
+
+        let mut statement = Statement {
+            as_of: reader.date(response, &["DTASOF"]),
+            account: response.text_at(&["INVACCTFROM", "ACCTID"]).map(|account| {
+                match response.text_at(&["INVACCTFROM", "BROKERID"]) {
+                    Some(broker) => format!("{} - {}", broker, account),
+                    None => account.to_string(),
+                }
+            }),
+            cash_currency: default_currency.clone(),
+            ..Default::default()
+        };
+
+        match response.at(&["INVBAL"]) {
+            Some(balance) => {
+                statement.cash = reader
+                    .decimal(balance, &["AVAILCASH"])
+                    .unwrap_or(Decimal::ZERO)
+            }
+            None => reader.warnings.push(ParseWarning::MissingCash),
+        }
+
+        let positions = response
+            .at(&["INVPOSLIST"])
+            .map(|list| list.children.as_slice())
+            .unwrap_or_default();
+        for position in positions {
+            if !POSITION_TYPES.contains(&position.name.as_str()) {
+                continue;
+            }
+            let Some(pos) = position.child("INVPOS") else {
+                reader.warnings.push(ParseWarning::MissingValue {
+                    line: position.line,
+                    column: "INVPOS".to_string(),
+                });
+                continue;
+            };
+            let Some(id) = pos.text_at(&["SECID", "UNIQUEID"]) else {
+                reader.warnings.push(ParseWarning::MissingValue {
+                    line: pos.line,
+                    column: "UNIQUEID".to_string(),
+                });
+                continue;
+            };
+            let Some(market_value) = reader.decimal(pos, &["MKTVAL"]) else {
+                continue;
+            };
+            let security = securities.get(id);
+            let currency = pos
+                .text_at(&["CURRENCY", "CURSYM"])
+                .or_else(|| pos.text_at(&["ORIGCURRENCY", "CURSYM"]))
+                .map(str::to_uppercase)
+                .or_else(|| default_currency.clone());
+
+            statement.holdings.push(Holding {
+                symbol: security.and_then(|s| s.ticker).unwrap_or(id).to_string(),
+                market_value,
+                description: security.and_then(|s| s.name).map(str::to_string),
+                quantity: reader.optional_decimal(pos, &["UNITS"]),
+                price: reader.optional_decimal(pos, &["UNITPRICE"]),
+                currency,
+                ..Default::default()
+            });
+        }
+
+        Ok(statement)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statement::parse_timestamp;
+    use std::str::FromStr;
+
+    const SGML_STATEMENT: &str = "OFXHEADER:100\r
+DATA:OFXSGML\r
+VERSION:102\r
+\r
+<OFX>\r
+<SIGNONMSGSRSV1><SONRS><STATUS><CODE>0<SEVERITY>INFO</STATUS>\r
+<DTSERVER>20250524143925.000[-4:EDT]<LANGUAGE>ENG</SONRS></SIGNONMSGSRSV1>\r
+<INVSTMTMSGSRSV1><INVSTMTTRNRS><TRNUID>1\r
+<INVSTMTRS>\r
+<DTASOF>20250524143925.000[-4:EDT]\r
+<CURDEF>CAD\r
+<INVACCTFROM><BROKERID>example.com<ACCTID>123456789</INVACCTFROM>\r
+<INVPOSLIST>\r
+<POSMF><INVPOS>\r
+<SECID><UNIQUEID>922908728<UNIQUEIDTYPE>CUSIP</SECID>\r
+<HELDINACCT>CASH<POSTYPE>LONG\r
+<UNITS>500.123<UNITPRICE>50.00<MKTVAL>25006.15\r
+<DTPRICEASOF>20250524\r
+</INVPOS></POSMF>\r
+<POSSTOCK><INVPOS>\r
+<SECID><UNIQUEID>922908769<UNIQUEIDTYPE>CUSIP</SECID>\r
+<HELDINACCT>CASH<POSTYPE>LONG\r
+<UNITS>10<UNITPRICE>300.00<MKTVAL>3000.00\r
+<DTPRICEASOF>20250524\r
+<CURRENCY><CURRATE>1.37<CURSYM>USD</CURRENCY>\r
+</INVPOS></POSSTOCK>\r
+</INVPOSLIST>\r
+<INVBAL><AVAILCASH>2500.00<MARGINBALANCE>0<SHORTBALANCE>0</INVBAL>\r
+</INVSTMTRS>\r
+</INVSTMTTRNRS></INVSTMTMSGSRSV1>\r
+<SECLISTMSGSRSV1><SECLIST>\r
+<MFINFO><SECINFO><SECID><UNIQUEID>922908728<UNIQUEIDTYPE>CUSIP</SECID>\r
+<SECNAME>Vanguard Total Stock Market<TICKER>VTSAX</SECINFO></MFINFO>\r
+<STOCKINFO><SECINFO><SECID><UNIQUEID>922908769<UNIQUEIDTYPE>CUSIP</SECID>\r
+<SECNAME>Vanguard Total Stock Market ETF<TICKER>VTI</SECINFO></STOCKINFO>\r
+</SECLIST></SECLISTMSGSRSV1>\r
+</OFX>\r
+";
+
+    #[test]
+    fn test_parse_ofx_sgml() {
+        assert!(OfxParser.detect(SGML_STATEMENT.as_bytes()));
+
+        let mut warnings = Vec::new();
+        let statement = OfxParser
+            .parse(
+                SGML_STATEMENT.as_bytes(),
+                &MoneyFormat::default(),
+                &mut warnings,
+            )
+            .unwrap();
+
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(statement.as_of, parse_timestamp("2025-05-24 14:39:25"));
+        assert_eq!(
+            statement.account.as_deref(),
+            Some("example.com - 123456789")
+        );
+        assert_eq!(statement.cash, Decimal::from_str("2500.00").unwrap());
+        assert_eq!(statement.cash_currency.as_deref(), Some("CAD"));
+        assert_eq!(statement.investments, None);
+
+        assert_eq!(statement.holdings.len(), 2);
+        let vtsax = &statement.holdings[0];
+        assert_eq!(vtsax.symbol, "VTSAX");
+        assert_eq!(
+            vtsax.description.as_deref(),
+            Some("Vanguard Total Stock Market")
+        );
+        assert_eq!(vtsax.market_value, Decimal::from_str("25006.15").unwrap());
+        assert_eq!(vtsax.quantity, Some(Decimal::from_str("500.123").unwrap()));
+        assert_eq!(vtsax.price, Some(Decimal::from_str("50.00").unwrap()));
+        assert_eq!(vtsax.currency.as_deref(), Some("CAD"));
+        assert_eq!(statement.holdings[1].symbol, "VTI");
+        assert_eq!(statement.holdings[1].currency.as_deref(), Some("USD"));
+    }
+
+    #[test]
+    fn test_parse_ofx_xml() {
+        let xml = r#"<?xml version="1.0" encoding="UTF-8"?>
+<?OFX OFXHEADER="200" VERSION="220"?>
+<OFX>
+  <INVSTMTMSGSRSV1>
+    <INVSTMTTRNRS>
+      <INVSTMTRS>
+        <DTASOF>20250524</DTASOF>
+        <CURDEF>USD</CURDEF>
+        <INVACCTFROM><ACCTID>987654321</ACCTID></INVACCTFROM>
+        <INVPOSLIST>
+          <POSMF>
+            <INVPOS>
+              <SECID><UNIQUEID>US9229087286</UNIQUEID><UNIQUEIDTYPE>ISIN</UNIQUEIDTYPE></SECID>
+              <MKTVAL>1000.50</MKTVAL>
+              <MEMO>Smith &amp; Co</MEMO>
+            </INVPOS>
+          </POSMF>
+        </INVPOSLIST>
+        <INVBAL><AVAILCASH>-12.34</AVAILCASH></INVBAL>
+      </INVSTMTRS>
+    </INVSTMTTRNRS>
+  </INVSTMTMSGSRSV1>
+</OFX>
+"#;
+        assert!(OfxParser.detect(xml.as_bytes()));
+
+        let mut warnings = Vec::new();
+        let statement = OfxParser
+            .parse(xml.as_bytes(), &MoneyFormat::default(), &mut warnings)
+            .unwrap();
+
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(statement.as_of, parse_timestamp("2025-05-24"));
+        assert_eq!(statement.account.as_deref(), Some("987654321"));
+        assert_eq!(statement.cash, Decimal::from_str("-12.34").unwrap());
+        // No SECLIST entry, so the position keeps its UNIQUEID
+        assert_eq!(statement.holdings[0].symbol, "US9229087286");
+        assert_eq!(statement.holdings[0].currency.as_deref(), Some("USD"));
+        assert_eq!(
+            decode_entities("Smith &amp; Co &lt;1&gt;"),
+            "Smith & Co <1>"
+        );
+    }
+
+    #[test]
+    fn test_parse_ofx_reports_bad_positions() {
+        let ofx = "<OFX>
+<INVSTMTRS>
+<INVPOSLIST>
+<POSMF><INVPOS><SECID><UNIQUEID>AAA</SECID><MKTVAL>N/A</INVPOS></POSMF>
+<POSMF><INVPOS><SECID><UNIQUEID>BBB</SECID><UNITS>5</INVPOS></POSMF>
+<POSMF><INVPOS><SECID><UNIQUEID>CCC</SECID><MKTVAL>10.00</INVPOS></POSMF>
+</INVPOSLIST>
+</INVSTMTRS>
+</OFX>";
+        let mut warnings = Vec::new();
+        let statement = OfxParser
+            .parse(ofx.as_bytes(), &MoneyFormat::default(), &mut warnings)
+            .unwrap();
+
+        assert_eq!(statement.holdings.len(), 1);
+        assert_eq!(statement.holdings[0].symbol, "CCC");
+        assert_eq!(
+            warnings,
+            vec![
+                ParseWarning::MissingCash,
+                ParseWarning::InvalidValue {
+                    line: 4,
+                    column: "MKTVAL".to_string(),
+                    text: "N/A".to_string(),
+                },
+                ParseWarning::MissingValue {
+                    line: 5,
+                    column: "MKTVAL".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_ofx_errors() {
+        let mut warnings = Vec::new();
+        let money = MoneyFormat::default();
+
+        assert_eq!(
+            OfxParser.parse(
+                b"<OFX><SIGNONMSGSRSV1></SIGNONMSGSRSV1></OFX>",
+                &money,
+                &mut warnings
+            ),
+            Err(ParseError::MissingElement {
+                element: "INVSTMTRS".to_string()
+            })
+        );
+        assert!(matches!(
+            OfxParser.parse(b"<OFX>\n<INVSTMTRS></INVPOSLIST>", &money, &mut warnings),
+            Err(ParseError::Malformed { line: Some(2), .. })
+        ));
+        assert!(!OfxParser.detect(b"Symbol,Market Value\nCASH,100.00\n"));
+    }
+}
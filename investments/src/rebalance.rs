@@ -0,0 +1,1478 @@
+use anyhow::{bail, Result};
+use rust_decimal::prelude::ToPrimitive;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::allocation::{self, Class, Mode, Unmanaged};
+use crate::fx::FxRates;
+use crate::settlement::Schedule;
+use crate::statement::Holding;
+use crate::Config;
+
+/// One account's cash and holdings, converted to the base currency.
+#[derive(Debug, Clone, Default)]
+pub struct Account {
+    pub id: String,
+    /// Account type, such as `rrsp` or `taxable`
+    pub kind: Option<String>,
+    pub cash: Decimal,
+    pub holdings: Vec<Holding>,
+}
+
+impl Account {
+    /// Market value of `symbol` held in this account.
+    fn value_of(&self, symbol: &str) -> Decimal {
+        self.holdings
+            .iter()
+            .filter(|h| h.symbol == symbol)
+            .map(|h| h.market_value)
+            .sum()
+    }
+
+    /// Whether the account is of type `kind`, ignoring case.
+    pub fn is_kind(&self, kind: &str) -> bool {
+        self.kind
+            .as_deref()
+            .is_some_and(|k| k.eq_ignore_ascii_case(kind))
+    }
+}
+
+/// An `[[asset_location]]` rule: the account types an asset class should be
+/// held in. Rules listed first get first claim on room in those accounts.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LocationRule {
+    /// Name of the asset class. With `[asset_classes]`, this is a class path
+    /// such as `equity.international`, and covers its subclasses' funds.
+    pub class: String,
+    /// Funds the rule applies to; defaults to the funds of `class`
+    #[serde(default)]
+    pub funds: Vec<String>,
+    /// Account types the class is preferred in, most preferred first
+    pub accounts: Vec<String>,
+}
+
+impl LocationRule {
+    /// Problems with the rule at `index` in `[[asset_location]]`, each with
+    /// its TOML key path. A `class` that is not a configured class is only a
+    /// label, and then `funds` must be listed.
+    pub fn errors(&self, index: usize, classes: &[Class]) -> Vec<String> {
+        let key = format!("asset_location[{}]", index);
+        let mut errors = Vec::new();
+        let class_funds: Vec<&String> = classes
+            .iter()
+            .filter(|class| class.is_in(&self.class))
+            .flat_map(|class| &class.funds)
+            .collect();
+        if class_funds.is_empty() && self.funds.is_empty() {
+            errors.push(format!(
+                "{}.class: '{}' is not a fund or asset class with a target, and no funds are listed",
+                key, self.class
+            ));
+        }
+        for (i, fund) in self.funds.iter().enumerate() {
+            if !classes.iter().any(|class| class.funds.contains(fund)) {
+                errors.push(format!(
+                    "{}.funds[{}]: {} is not a fund listed with a target",
+                    key, i, fund
+                ));
+            } else if !class_funds.is_empty() && !class_funds.contains(&fund) {
+                errors.push(format!(
+                    "{}.funds[{}]: {} is not a fund of {}",
+                    key, i, fund, self.class
+                ));
+            }
+        }
+        if self.accounts.is_empty() {
+            errors.push(format!("{}.accounts: no account types listed", key));
+        }
+        for (i, kind) in self.accounts.iter().enumerate() {
+            if !allocation::is_name(kind) {
+                errors.push(format!(
+                    "{}.accounts[{}]: '{}' is not an account type (use letters, digits, '_' or '-')",
+                    key, i, kind
+                ));
+            }
+        }
+        errors
+    }
+
+    fn funds<'a>(&'a self, classes: &'a [Class]) -> Vec<&'a str> {
+        if !self.funds.is_empty() {
+            return self.funds.iter().map(String::as_str).collect();
+        }
+        classes
+            .iter()
+            .filter(|class| class.is_in(&self.class))
+            .flat_map(|class| class.funds.iter().map(String::as_str))
+            .collect()
+    }
+}
+
+#[derive(Debug)]
+pub struct Order {
+    pub account: String,
+    pub fund: String,
+    pub action: String,
+    pub amount: Decimal,
+    pub currency: String,
+    /// Shares to trade, when the fund's price is known
+    pub quantity: Option<Decimal>,
+    /// Phase and dates, once the orders are scheduled around settlement
+    pub schedule: Option<Schedule>,
+}
+
+/// How orders are sorted within each account, after sells are put first.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SortBy {
+    /// The order the config lists the funds in
+    #[default]
+    Config,
+    /// Largest amount first
+    Amount,
+    Symbol,
+}
+
+/// The `[orders]` table.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct OrdersConfig {
+    pub sort_by: SortBy,
+}
+
+/// Part of a fund's household target that could not be placed as configured.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shortfall {
+    /// The preferred accounts had no room, so `amount` was held elsewhere.
+    NotPreferred {
+        fund: String,
+        class: String,
+        accounts: Vec<String>,
+        amount: Decimal,
+    },
+}
+
+impl fmt::Display for Shortfall {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Shortfall::NotPreferred {
+                fund,
+                class,
+                accounts,
+                amount,
+            } => write!(
+                f,
+                "${:.2} of {} ({}) does not fit in {} accounts and is held elsewhere",
+                amount,
+                fund,
+                class,
+                accounts.join("/")
+            ),
+        }
+    }
+}
+
+/// A holding of a fund that has no target in the config.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnmanagedHolding {
+    pub account: String,
+    pub fund: String,
+    /// Market value, in the base currency
+    pub value: Decimal,
+    /// Whether it is sold, rather than left out of the portfolio
+    pub liquidated: bool,
+}
+
+impl fmt::Display for UnmanagedHolding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} (${:.2} in account {})",
+            self.fund, self.value, self.account
+        )
+    }
+}
+
+/// The orders for each account, plus anything that could not be placed.
+#[derive(Debug, Default)]
+pub struct Plan {
+    /// Cash held back from the allocation, in the base currency
+    pub reserve: Decimal,
+    pub orders: Vec<Order>,
+    pub shortfalls: Vec<Shortfall>,
+    /// Holdings without a target, handled as `[targets]` `unmanaged_holdings` says
+    pub unmanaged: Vec<UnmanagedHolding>,
+}
+
+/// Tracks how much of each fund's target is still to be placed and how much
+/// room is left in each account.
+struct Placement<'a> {
+    accounts: &'a [Account],
+    remaining: HashMap<&'a str, Decimal>,
+    room: Vec<Decimal>,
+    placed: Vec<HashMap<&'a str, Decimal>>,
+}
+
+impl<'a> Placement<'a> {
+    /// Places as much of `fund`'s remaining target in the account at `index`
+    /// as its room and `limit` allow.
+    fn place(&mut self, index: usize, fund: &'a str, limit: Option<Decimal>) {
+        let remaining = self.remaining.get_mut(fund).expect("fund has a target");
+        let mut amount = (*remaining).min(self.room[index]);
+        if let Some(limit) = limit {
+            amount = amount.min(limit);
+        }
+        if amount <= Decimal::ZERO {
+            return;
+        }
+        *remaining -= amount;
+        self.room[index] -= amount;
+        *self.placed[index].entry(fund).or_default() += amount;
+    }
+
+    /// Account indexes to try for `fund`: accounts already holding it first,
+    /// then in input order.
+    fn candidates(&self, fund: &str, indexes: impl Iterator<Item = usize>) -> Vec<usize> {
+        let mut indexes: Vec<usize> = indexes.collect();
+        indexes.sort_by_key(|&index| self.accounts[index].value_of(fund).is_zero());
+        indexes
+    }
+
+    /// Places each fund's target in the account types its location rule
+    /// prefers, reporting whatever does not fit.
+    fn prefer(
+        &mut self,
+        rules: &[LocationRule],
+        classes: &[Class],
+        funds: &[&'a str],
+        shortfalls: &mut Vec<Shortfall>,
+    ) {
+        for rule in rules {
+            for fund in rule.funds(classes) {
+                let Some(fund) = funds.iter().copied().find(|&f| f == fund) else {
+                    continue;
+                };
+                for kind in &rule.accounts {
+                    let of_kind = (0..self.accounts.len())
+                        .filter(|&index| self.accounts[index].is_kind(kind));
+                    for index in self.candidates(fund, of_kind) {
+                        self.place(index, fund, None);
+                    }
+                }
+                let amount = self.remaining[fund];
+                if amount > Decimal::ZERO {
+                    shortfalls.push(Shortfall::NotPreferred {
+                        fund: fund.to_string(),
+                        class: rule.class.clone(),
+                        accounts: rule.accounts.clone(),
+                        amount,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Keeps each fund's target where the fund is already held.
+    fn keep(&mut self, funds: &[&'a str]) {
+        for &fund in funds {
+            for index in 0..self.accounts.len() {
+                let held = self.accounts[index].value_of(fund);
+                self.place(index, fund, Some(held));
+            }
+        }
+    }
+
+    /// Puts the rest of each fund's target wherever there is room. The
+    /// accounts' room adds up to the household value, which the targets never
+    /// exceed, so everything fits somewhere.
+    fn fill(&mut self, funds: &[&'a str]) {
+        for &fund in funds {
+            for index in self.candidates(fund, 0..self.accounts.len()) {
+                self.place(index, fund, None);
+            }
+        }
+    }
+}
+
+/// Calculates the orders that move the household's combined holdings toward
+/// the target allocation, without moving money between accounts.
+///
+/// Targets are rebalanced per asset class, within each class's band, and then
+/// split among the class's funds, as in [`allocation::fund_targets`]. Each
+/// fund's household target is first placed in the account types its
+/// `[[asset_location]]` rule prefers, then kept where it is already held, and
+/// finally put wherever room is left. In the buy-only and sell-only modes,
+/// holdings are kept in place first, so that only the cash flow is placed by
+/// preference. `flow` is the net contribution (negative for a withdrawal),
+/// already included in the accounts' cash. The orders for each account are
+/// the difference between what was placed there and what it holds, so each
+/// account's buys are paid for with its own cash and sale proceeds.
+///
+/// Holdings of funds without a target are an error, sold in full, or left
+/// out of the portfolio, as `unmanaged_holdings` in `[targets]` says, and are
+/// listed in the plan either way.
+pub fn calculate_orders(
+    config: &Config,
+    accounts: &[Account],
+    mode: Mode,
+    flow: Decimal,
+) -> Result<Plan> {
+    let classes = allocation::classes(config)?;
+    let policy = config.targets.unmanaged_holdings;
+    // Buy-only mode never sells, so it leaves out even holdings to liquidate
+    let liquidate = policy == Unmanaged::Liquidate && mode != Mode::BuyOnly;
+    let unmanaged: Vec<UnmanagedHolding> = accounts
+        .iter()
+        .flat_map(|account| {
+            account
+                .holdings
+                .iter()
+                .filter(|h| !is_managed(&classes, &h.symbol))
+                .map(|h| UnmanagedHolding {
+                    account: account.id.clone(),
+                    fund: h.symbol.clone(),
+                    value: h.market_value,
+                    liquidated: liquidate,
+                })
+        })
+        .collect();
+    if policy == Unmanaged::Error && !unmanaged.is_empty() {
+        let list: Vec<String> = unmanaged.iter().map(ToString::to_string).collect();
+        bail!(
+            "Holdings not in the config: {}. Add them to the config, or set \
+             unmanaged_holdings in [targets] to \"liquidate\" or \"ignore\"",
+            list.join(", ")
+        );
+    }
+
+    // Unmanaged holdings are taken out of the accounts: liquidated ones are
+    // sold for cash, and the rest are no longer part of the portfolio
+    let mut liquidations: Vec<Vec<Order>> = Vec::new();
+    let managed: Vec<Account> = accounts
+        .iter()
+        .map(|account| {
+            let mut account = account.clone();
+            let (kept, sold): (Vec<Holding>, Vec<Holding>) = account
+                .holdings
+                .into_iter()
+                .partition(|h| is_managed(&classes, &h.symbol));
+            account.holdings = kept;
+            let mut orders = Vec::new();
+            if liquidate {
+                for holding in sold {
+                    account.cash += holding.market_value;
+                    orders.push(Order {
+                        account: account.id.clone(),
+                        fund: holding.symbol,
+                        action: "SELL".to_string(),
+                        amount: holding.market_value,
+                        currency: config.currency.base_currency.clone(),
+                        quantity: holding.quantity,
+                        schedule: None,
+                    });
+                }
+            }
+            liquidations.push(orders);
+            account
+        })
+        .collect();
+    let accounts = &managed[..];
+
+    // A withdrawal cannot take more than an account is worth, since money
+    // never moves between accounts
+    if flow < Decimal::ZERO {
+        for account in accounts {
+            let value = account.cash
+                + account
+                    .holdings
+                    .iter()
+                    .map(|h| h.market_value)
+                    .sum::<Decimal>();
+            if value < Decimal::ZERO {
+                bail!(
+                    "The withdrawal from account {} is ${:.2} more than its cash and holdings",
+                    account.id,
+                    -value
+                );
+            }
+        }
+    }
+
+    // Calculate total household value
+    let total_value: Decimal = accounts
+        .iter()
+        .map(|a| a.cash + a.holdings.iter().map(|h| h.market_value).sum::<Decimal>())
+        .sum();
+
+    // Targets apply to what is left once the cash reserve is held back
+    let reserve = config.cash_reserve.of(total_value);
+    let cash = accounts.iter().map(|a| a.cash).sum::<Decimal>() - reserve;
+    if mode == Mode::BuyOnly && flow < Decimal::ZERO && cash < Decimal::ZERO {
+        bail!(
+            "The withdrawal needs ${:.2} of sales, which --mode buy-only does not allow",
+            -cash
+        );
+    }
+    let targets = allocation::fund_targets(
+        &classes,
+        total_value - reserve,
+        cash,
+        flow,
+        &config.bands,
+        mode,
+        |fund| accounts.iter().map(|a| a.value_of(fund)).sum(),
+    );
+    let min_trades: HashMap<&str, Decimal> = classes
+        .iter()
+        .flat_map(|class| {
+            let min_trade = config.bands.band(class).min_trade;
+            class
+                .funds
+                .iter()
+                .map(move |fund| (fund.as_str(), min_trade))
+        })
+        .collect();
+
+    // Sorted so that placement does not depend on hash order
+    let mut funds: Vec<&str> = targets.keys().map(String::as_str).collect();
+    funds.sort();
+
+    let mut placement = Placement {
+        accounts,
+        remaining: funds.iter().map(|&fund| (fund, targets[fund])).collect(),
+        room: accounts
+            .iter()
+            .map(|a| a.cash + funds.iter().map(|fund| a.value_of(fund)).sum::<Decimal>())
+            .collect(),
+        placed: vec![HashMap::new(); accounts.len()],
+    };
+    let mut shortfalls = Vec::new();
+
+    if mode == Mode::Full {
+        placement.prefer(&config.asset_location, &classes, &funds, &mut shortfalls);
+        placement.keep(&funds);
+    } else {
+        placement.keep(&funds);
+        placement.prefer(&config.asset_location, &classes, &funds, &mut shortfalls);
+    }
+    placement.fill(&funds);
+
+    let mut orders = Vec::new();
+    let placements = placement.placed.iter().zip(&placement.room);
+    for ((account, (placed, room)), sales) in accounts.iter().zip(placements).zip(liquidations) {
+        let mut account_orders = Vec::new();
+        for &fund in &funds {
+            let target_value = placed.get(fund).copied().unwrap_or_default();
+            let difference = target_value - account.value_of(fund);
+
+            // Skip orders no larger than the fund's minimum trade
+            if difference.abs() <= min_trades[fund] {
+                continue;
+            }
+            let action = if difference > Decimal::ZERO {
+                "BUY"
+            } else {
+                "SELL"
+            };
+            account_orders.push(Order {
+                account: account.id.clone(),
+                fund: fund.to_string(),
+                action: action.to_string(),
+                amount: difference.abs(),
+                currency: config.currency.base_currency.clone(),
+                quantity: None,
+                schedule: None,
+            });
+        }
+        // Room left unplaced is the account's share of the cash reserve (and
+        // of any cash target), which the buys must not spend
+        fit_to_cash(&mut account_orders, account.cash - room);
+        orders.extend(sales);
+        orders.extend(account_orders);
+    }
+
+    Ok(Plan {
+        reserve,
+        orders,
+        shortfalls,
+        unmanaged,
+    })
+}
+
+/// Whether `fund` has a target in any class.
+fn is_managed(classes: &[Class], fund: &str) -> bool {
+    classes
+        .iter()
+        .any(|class| class.funds.iter().any(|f| f == fund))
+}
+
+/// Puts the orders in a fixed sequence: each account's orders together, in
+/// the order the accounts were given, with sells before buys so that their
+/// proceeds pay for the buys, and then sorted as `sort_by` says. Amounts are
+/// compared in the base currency. Funds not in `fund_order` come after those
+/// that are, by symbol.
+pub fn sequence_orders(
+    orders: &mut Vec<Order>,
+    sort_by: SortBy,
+    fund_order: &[&str],
+    fx: &FxRates,
+) -> Result<()> {
+    let mut accounts: Vec<String> = Vec::new();
+    for order in orders.iter() {
+        if !accounts.contains(&order.account) {
+            accounts.push(order.account.clone());
+        }
+    }
+    let position = |fund: &str| {
+        fund_order
+            .iter()
+            .position(|&f| f == fund)
+            .unwrap_or(fund_order.len())
+    };
+    let mut valued = orders
+        .drain(..)
+        .map(|order| Ok((fx.to_base(order.amount, &order.currency)?, order)))
+        .collect::<Result<Vec<(Decimal, Order)>>>()?;
+    valued.sort_by(|(a_value, a), (b_value, b)| {
+        let account = |order: &Order| accounts.iter().position(|id| *id == order.account);
+        let then = match sort_by {
+            SortBy::Config => position(&a.fund).cmp(&position(&b.fund)),
+            SortBy::Amount => b_value.cmp(a_value),
+            SortBy::Symbol => std::cmp::Ordering::Equal,
+        };
+        account(a)
+            .cmp(&account(b))
+            .then((a.action != "SELL").cmp(&(b.action != "SELL")))
+            .then(then)
+            .then(a.fund.cmp(&b.fund))
+    });
+    orders.extend(valued.into_iter().map(|(_, order)| order));
+    Ok(())
+}
+
+/// Trims buys, largest first, if skipping small sells left the account's
+/// buys costing more than its free `cash` plus its sale proceeds. Buys trimmed to
+/// nothing are dropped.
+fn fit_to_cash(orders: &mut Vec<Order>, cash: Decimal) {
+    let net: Decimal = orders.iter().map(net_cash).sum();
+    let mut excess = net - cash;
+    if excess <= Decimal::ZERO {
+        return;
+    }
+    let mut buys: Vec<&mut Order> = orders.iter_mut().filter(|o| o.action == "BUY").collect();
+    buys.sort_by_key(|buy| std::cmp::Reverse(buy.amount));
+    for buy in buys {
+        if excess <= Decimal::ZERO {
+            break;
+        }
+        let trim = buy.amount.min(excess);
+        buy.amount -= trim;
+        excess -= trim;
+    }
+    orders.retain(|order| !order.amount.is_zero());
+}
+
+/// The net cash each account plans to use in each currency, by account and
+/// currency: its buys minus its sells.
+pub fn planned_cash(orders: &[Order]) -> HashMap<(String, String), Decimal> {
+    let mut planned = HashMap::new();
+    for order in orders {
+        *planned
+            .entry((order.account.clone(), order.currency.clone()))
+            .or_default() += net_cash(order);
+    }
+    planned
+}
+
+/// Rounds order amounts to the cent so that each account's net cash used in
+/// each currency is its `planned` net, from [`planned_cash`], rounded to the
+/// cent.
+///
+/// Every amount is first rounded down (sells away from zero), and the cents
+/// this leaves over go one each to the orders that lost the most in rounding,
+/// earlier orders first on a tie (the largest remainder method). Orders that
+/// round to nothing are dropped. It is an error if the orders cannot come to
+/// the planned net, because they no longer match the plan.
+pub fn round_to_cents(
+    orders: &mut Vec<Order>,
+    planned: &HashMap<(String, String), Decimal>,
+) -> Result<()> {
+    let cent = Decimal::new(1, 2);
+    let mut groups: Vec<(&str, &str)> = Vec::new();
+    for order in orders.iter() {
+        if !groups.contains(&(&order.account, &order.currency)) {
+            groups.push((&order.account, &order.currency));
+        }
+    }
+    let groups: Vec<(String, String)> = groups
+        .into_iter()
+        .map(|(account, currency)| (account.to_string(), currency.to_string()))
+        .collect();
+
+    for (account, currency) in &groups {
+        let indexes: Vec<usize> = (0..orders.len())
+            .filter(|&i| &orders[i].account == account && &orders[i].currency == currency)
+            .collect();
+        let planned = planned
+            .get(&(account.clone(), currency.clone()))
+            .copied()
+            .unwrap_or_default()
+            .round_dp(2);
+
+        let mut remainders = Vec::new();
+        for &i in &indexes {
+            let exact = net_cash(&orders[i]);
+            let floor = exact.round_dp_with_strategy(2, RoundingStrategy::ToNegativeInfinity);
+            remainders.push((i, exact - floor));
+            orders[i].amount = floor.abs();
+        }
+        let rounded: Decimal = indexes.iter().map(|&i| net_cash(&orders[i])).sum();
+        let extra_cents = ((planned - rounded) / cent)
+            .to_usize()
+            .unwrap_or_default()
+            .min(indexes.len());
+        remainders.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        for &(i, _) in remainders.iter().take(extra_cents) {
+            orders[i].amount = (net_cash(&orders[i]) + cent).abs();
+        }
+
+        let used: Decimal = indexes.iter().map(|&i| net_cash(&orders[i])).sum();
+        if used != planned {
+            bail!(
+                "Orders for account {} use {} {} of cash, but {} was planned",
+                account,
+                used,
+                currency,
+                planned
+            );
+        }
+    }
+
+    orders.retain(|order| !order.amount.is_zero());
+    Ok(())
+}
+
+/// The cash an order uses: its amount for a buy, minus its amount for a sell.
+fn net_cash(order: &Order) -> Decimal {
+    match order.action.as_str() {
+        "BUY" => order.amount,
+        _ => -order.amount,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allocation::{CashReserve, Unmanaged};
+    use indexmap::IndexMap;
+    use std::str::FromStr;
+
+    fn holding(symbol: &str, market_value: &str) -> Holding {
+        Holding {
+            symbol: symbol.to_string(),
+            market_value: Decimal::from_str(market_value).unwrap(),
+            ..Default::default()
+        }
+    }
+
+    fn account(id: &str, cash: &str, holdings: Vec<Holding>) -> Account {
+        Account {
+            id: id.to_string(),
+            cash: Decimal::from_str(cash).unwrap(),
+            holdings,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_calculate_orders() {
+        let mut funds = IndexMap::new();
+        funds.insert("ABC123".to_string(), Decimal::from(60));
+        funds.insert("ABC456".to_string(), Decimal::from(30));
+        funds.insert("ABC789".to_string(), Decimal::from(10));
+        let config = Config {
+            funds,
+            ..Default::default()
+        };
+
+        let holdings = vec![
+            holding("ABC123", "6000"),
+            holding("ABC456", "2000"),
+            holding("ABC789", "1000"),
+        ];
+
+        let orders = calculate_orders(
+            &config,
+            &[account("main", "1000", holdings)],
+            Mode::Full,
+            Decimal::ZERO,
+        )
+        .unwrap()
+        .orders;
+
+        // Total value: 6000 + 2000 + 1000 + 1000 = 10000
+        // Target ABC123: 10000 * 0.6 = 6000 (current: 6000, diff: 0)
+        // Target ABC456: 10000 * 0.3 = 3000 (current: 2000, diff: +1000)
+        // Target ABC789: 10000 * 0.1 = 1000 (current: 1000, diff: 0)
+
+        assert_eq!(orders.len(), 1);
+        assert_eq!(orders[0].fund, "ABC456");
+        assert_eq!(orders[0].action, "BUY");
+        assert_eq!(orders[0].amount, Decimal::from(1000));
+        assert_eq!(orders[0].account, "main");
+    }
+
+    #[test]
+    fn test_calculate_orders_with_sells() {
+        let mut funds = IndexMap::new();
+        funds.insert("ABC123".to_string(), Decimal::from(30));
+        funds.insert("ABC456".to_string(), Decimal::from(30));
+        funds.insert("ABC789".to_string(), Decimal::from(40));
+        let config = Config {
+            funds,
+            ..Default::default()
+        };
+
+        let holdings = vec![
+            holding("ABC123", "6000"),
+            holding("ABC456", "2000"),
+            holding("ABC789", "1000"),
+        ];
+
+        let orders = calculate_orders(
+            &config,
+            &[account("main", "1000", holdings)],
+            Mode::Full,
+            Decimal::ZERO,
+        )
+        .unwrap()
+        .orders;
+
+        // Total value: 10000
+        // Target ABC123: 10000 * 0.3 = 3000 (current: 6000, diff: -3000)
+        // Target ABC456: 10000 * 0.3 = 3000 (current: 2000, diff: +1000)
+        // Target ABC789: 10000 * 0.4 = 4000 (current: 1000, diff: +3000)
+
+        assert_eq!(orders.len(), 3);
+
+        let abc123_order = orders.iter().find(|o| o.fund == "ABC123").unwrap();
+        assert_eq!(abc123_order.action, "SELL");
+        assert_eq!(abc123_order.amount, Decimal::from(3000));
+
+        let abc456_order = orders.iter().find(|o| o.fund == "ABC456").unwrap();
+        assert_eq!(abc456_order.action, "BUY");
+        assert_eq!(abc456_order.amount, Decimal::from(1000));
+
+        let abc789_order = orders.iter().find(|o| o.fund == "ABC789").unwrap();
+        assert_eq!(abc789_order.action, "BUY");
+        assert_eq!(abc789_order.amount, Decimal::from(3000));
+    }
+
+    #[test]
+    fn test_calculate_orders_ignores_small_differences() {
+        let mut funds = IndexMap::new();
+        funds.insert("ABC123".to_string(), Decimal::from(60));
+        funds.insert("cash".to_string(), Decimal::from(40));
+        let config = Config {
+            funds,
+            ..Default::default()
+        };
+
+        let accounts = [account("main", "0", vec![holding("ABC123", "6000.50")])];
+        let orders = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO)
+            .unwrap()
+            .orders;
+
+        // Total value: 6000.50
+        // Target ABC123: 6000.50 * 0.6 = 3600.30 (current: 6000.50, diff: -2400.20)
+        // Should create order since difference > $1
+        assert_eq!(orders.len(), 1);
+
+        // Test with small difference
+        let accounts = [account("main", "0.50", vec![holding("ABC123", "5999.50")])];
+        let orders_small = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO)
+            .unwrap()
+            .orders;
+        // Total: 6000, Target: 3600, Current: 5999.50, diff: -2399.50 > $1
+        assert_eq!(orders_small.len(), 1);
+
+        // Test with very small difference
+        let accounts = [account("main", "0.01", vec![holding("ABC123", "5999.99")])];
+        let orders_tiny = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO)
+            .unwrap()
+            .orders;
+        // Total: 6000, Target: 3600, Current: 5999.99, diff: -2399.99 > $1
+        assert_eq!(orders_tiny.len(), 1);
+
+        // A difference under $1 creates no order
+        let config = Config {
+            funds: IndexMap::from([("ABC123".to_string(), Decimal::from(100))]),
+            ..Default::default()
+        };
+        let accounts = [account("main", "0.99", vec![holding("ABC123", "100.50")])];
+        assert!(
+            calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO)
+                .unwrap()
+                .orders
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_calculate_orders_holds_back_cash_reserve() {
+        let config = Config {
+            funds: IndexMap::from([("ABC123".to_string(), Decimal::from(100))]),
+            cash_reserve: CashReserve {
+                amount: Some(Decimal::from(500)),
+                percent: None,
+            },
+            ..Default::default()
+        };
+        let accounts = [account("main", "1000", vec![holding("ABC123", "9000")])];
+
+        let plan = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO).unwrap();
+
+        // Total: 10000, of which 500 is held back; ABC123 9000 -> 9500
+        assert_eq!(plan.reserve, Decimal::from(500));
+        assert_eq!(
+            summary(&plan.orders),
+            vec![("main", "ABC123", "BUY", Decimal::from(500))]
+        );
+
+        // A reserve larger than the cash on hand is raised by selling
+        let accounts = [account("main", "0", vec![holding("ABC123", "10000")])];
+        let plan = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO).unwrap();
+        assert_eq!(
+            summary(&plan.orders),
+            vec![("main", "ABC123", "SELL", Decimal::from(500))]
+        );
+    }
+
+    #[test]
+    fn test_calculate_orders_within_bands() {
+        let mut config = Config {
+            funds: IndexMap::from([
+                ("ABC123".to_string(), Decimal::from(50)),
+                ("ABC456".to_string(), Decimal::from(30)),
+                ("ABC789".to_string(), Decimal::from(20)),
+            ]),
+            bands: toml::from_str("absolute = 5").unwrap(),
+            ..Default::default()
+        };
+        let accounts = [account(
+            "main",
+            "0",
+            vec![
+                holding("ABC123", "5800"),
+                holding("ABC456", "2700"),
+                holding("ABC789", "1500"),
+            ],
+        )];
+
+        // ABC123 is 8 points over and breaches its band; ABC456 (3 under) and
+        // ABC789 (5 under) are within theirs and are not traded
+        let orders = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO)
+            .unwrap()
+            .orders;
+        assert_eq!(
+            summary(&orders),
+            vec![("main", "ABC123", "SELL", Decimal::from(800))]
+        );
+
+        config.bands = toml::from_str("absolute = 5\nrebalance_to = \"edge\"").unwrap();
+        let orders = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO)
+            .unwrap()
+            .orders;
+        assert_eq!(
+            summary(&orders),
+            vec![("main", "ABC123", "SELL", Decimal::from(300))]
+        );
+
+        // Only 500 of cash for the 1000 ABC123 is short, since the funds over
+        // target are within their bands and are not sold
+        let accounts = [account(
+            "main",
+            "500",
+            vec![
+                holding("ABC123", "4000"),
+                holding("ABC456", "3300"),
+                holding("ABC789", "2200"),
+            ],
+        )];
+        config.bands = toml::from_str("absolute = 5\nmin_trade = 100").unwrap();
+        let orders = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO)
+            .unwrap()
+            .orders;
+        assert_eq!(
+            summary(&orders),
+            vec![("main", "ABC123", "BUY", Decimal::from(500))]
+        );
+
+        // Orders at or below the minimum trade are skipped
+        config.bands = toml::from_str("min_trade = 100\n[ABC123]\nmin_trade = 1000").unwrap();
+        let orders = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO)
+            .unwrap()
+            .orders;
+        assert_eq!(
+            summary(&orders),
+            vec![
+                ("main", "ABC456", "SELL", Decimal::from(300)),
+                ("main", "ABC789", "SELL", Decimal::from(200)),
+            ]
+        );
+
+        // Skipping the four small sells leaves 360 of the buys unfunded, more
+        // than the largest buy, so both buys are trimmed
+        let config = Config {
+            funds: IndexMap::from([
+                ("S1".to_string(), Decimal::from(10)),
+                ("S2".to_string(), Decimal::from(10)),
+                ("S3".to_string(), Decimal::from(10)),
+                ("S4".to_string(), Decimal::from(10)),
+                ("B1".to_string(), Decimal::from(30)),
+                ("B2".to_string(), Decimal::from(30)),
+            ]),
+            bands: toml::from_str("min_trade = 100").unwrap(),
+            ..Default::default()
+        };
+        let accounts = [account(
+            "main",
+            "250",
+            vec![
+                holding("S1", "1090"),
+                holding("S2", "1090"),
+                holding("S3", "1090"),
+                holding("S4", "1090"),
+                holding("B1", "2690"),
+                holding("B2", "2700"),
+            ],
+        )];
+        let orders = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO)
+            .unwrap()
+            .orders;
+        assert_eq!(
+            summary(&orders),
+            vec![("main", "B2", "BUY", Decimal::from(250))]
+        );
+
+        // The trimmed buys leave the cash reserve alone: of the 1000 of cash,
+        // 500 is held back, so only 500 of the 580 buy is left once the small
+        // sell of A is skipped
+        let config = Config {
+            funds: IndexMap::from([
+                ("A".to_string(), Decimal::from(40)),
+                ("B".to_string(), Decimal::from(30)),
+                ("C".to_string(), Decimal::from(30)),
+            ]),
+            bands: toml::from_str("min_trade = 100").unwrap(),
+            cash_reserve: CashReserve {
+                amount: Some(Decimal::from(500)),
+                percent: None,
+            },
+            ..Default::default()
+        };
+        let accounts = [account(
+            "main",
+            "1000",
+            vec![
+                holding("A", "3880"),
+                holding("B", "2850"),
+                holding("C", "2270"),
+            ],
+        )];
+        let orders = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO)
+            .unwrap()
+            .orders;
+        assert_eq!(
+            summary(&orders),
+            vec![("main", "C", "BUY", Decimal::from(500))]
+        );
+    }
+
+    #[test]
+    fn test_calculate_household_orders() {
+        let config = Config {
+            funds: IndexMap::from([
+                ("EQUITY".to_string(), Decimal::from(60)),
+                ("BONDS".to_string(), Decimal::from(40)),
+            ]),
+            ..Default::default()
+        };
+        let accounts = [
+            account("rrsp", "0", vec![holding("EQUITY", "6000")]),
+            account("tfsa", "1000", vec![holding("BONDS", "1000")]),
+            account("taxable", "2000", vec![]),
+        ];
+
+        let orders = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO)
+            .unwrap()
+            .orders;
+
+        // Household: 10000. EQUITY 6000 -> 6000 (no change), BONDS 1000 -> 4000
+        // The tfsa already holds BONDS, so it buys with its 1000 first and the
+        // taxable account buys the remaining 2000. The rrsp has no cash to spend.
+        let summary: Vec<(&str, &str, &str, Decimal)> = orders
+            .iter()
+            .map(|o| {
+                (
+                    o.account.as_str(),
+                    o.fund.as_str(),
+                    o.action.as_str(),
+                    o.amount,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("tfsa", "BONDS", "BUY", Decimal::from(1000)),
+                ("taxable", "BONDS", "BUY", Decimal::from(2000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_household_orders_stay_within_accounts() {
+        let config = Config {
+            funds: IndexMap::from([
+                ("EQUITY".to_string(), Decimal::from(50)),
+                ("BONDS".to_string(), Decimal::from(50)),
+            ]),
+            ..Default::default()
+        };
+        let accounts = [
+            account(
+                "rrsp",
+                "100",
+                vec![holding("EQUITY", "4000"), holding("BONDS", "1000")],
+            ),
+            account("tfsa", "0", vec![holding("EQUITY", "4900")]),
+        ];
+
+        let orders = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO)
+            .unwrap()
+            .orders;
+
+        // Household: 10000. EQUITY 8900 -> 5000 (sell 3900), BONDS 1000 -> 5000 (buy 4000)
+        for account in &accounts {
+            let bought: Decimal = orders
+                .iter()
+                .filter(|o| o.account == account.id && o.action == "BUY")
+                .map(|o| o.amount)
+                .sum();
+            let sold: Decimal = orders
+                .iter()
+                .filter(|o| o.account == account.id && o.action == "SELL")
+                .map(|o| o.amount)
+                .sum();
+            assert!(
+                bought <= account.cash + sold,
+                "{} buys {} with only {} available",
+                account.id,
+                bought,
+                account.cash + sold
+            );
+        }
+        let sold: Decimal = orders
+            .iter()
+            .filter(|o| o.fund == "EQUITY" && o.action == "SELL")
+            .map(|o| o.amount)
+            .sum();
+        let bought: Decimal = orders
+            .iter()
+            .filter(|o| o.fund == "BONDS" && o.action == "BUY")
+            .map(|o| o.amount)
+            .sum();
+        assert_eq!(sold, Decimal::from(3900));
+        assert_eq!(bought, Decimal::from(4000));
+    }
+
+    #[test]
+    fn test_buy_only_and_sell_only_modes() {
+        let mut config = Config {
+            funds: IndexMap::from([
+                ("EQUITY".to_string(), Decimal::from(50)),
+                ("BONDS".to_string(), Decimal::from(50)),
+            ]),
+            ..Default::default()
+        };
+        let accounts = [
+            account(
+                "rrsp",
+                "100",
+                vec![holding("EQUITY", "4000"), holding("BONDS", "1000")],
+            ),
+            account("tfsa", "0", vec![holding("EQUITY", "4900")]),
+        ];
+
+        // Only the rrsp's 100 of cash is spent, on the underweight BONDS
+        let plan = calculate_orders(&config, &accounts, Mode::BuyOnly, Decimal::ZERO).unwrap();
+        assert_eq!(
+            summary(&plan.orders),
+            vec![("rrsp", "BONDS", "BUY", Decimal::from(100))]
+        );
+
+        // No cash is missing, so nothing is sold
+        let plan = calculate_orders(&config, &accounts, Mode::SellOnly, Decimal::ZERO).unwrap();
+        assert!(plan.orders.is_empty());
+
+        // Raising a 1000 reserve sells 900 of the overweight EQUITY
+        config.cash_reserve.amount = Some(Decimal::from(1000));
+        let plan = calculate_orders(&config, &accounts, Mode::SellOnly, Decimal::ZERO).unwrap();
+        assert_eq!(
+            summary(&plan.orders),
+            vec![("tfsa", "EQUITY", "SELL", Decimal::from(900))]
+        );
+        let plan = calculate_orders(&config, &accounts, Mode::BuyOnly, Decimal::ZERO).unwrap();
+        assert!(plan.orders.is_empty());
+    }
+
+    #[test]
+    fn test_contributions_and_withdrawals() {
+        let config = Config {
+            funds: IndexMap::from([
+                ("ABC123".to_string(), Decimal::from(50)),
+                ("ABC456".to_string(), Decimal::from(30)),
+                ("ABC789".to_string(), Decimal::from(20)),
+            ]),
+            bands: toml::from_str("absolute = 5").unwrap(),
+            ..Default::default()
+        };
+        let holdings = vec![
+            holding("ABC123", "5000"),
+            holding("ABC456", "2800"),
+            holding("ABC789", "2200"),
+        ];
+
+        // Idle cash alone does not breach any band...
+        let accounts = [account("main", "1000", holdings.clone())];
+        let plan = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO).unwrap();
+        assert!(plan.orders.is_empty());
+
+        // ...but a contribution goes to the most underweight funds. Of 11000,
+        // ABC123 and ABC456 are each 500 under target and ABC789 is on it.
+        let plan = calculate_orders(&config, &accounts, Mode::Full, Decimal::from(1000)).unwrap();
+        assert_eq!(
+            summary(&plan.orders),
+            vec![
+                ("main", "ABC123", "BUY", Decimal::from(500)),
+                ("main", "ABC456", "BUY", Decimal::from(500)),
+            ]
+        );
+
+        // A 2000 withdrawal from 10000 is sold from the most overweight funds
+        let accounts = [account("main", "-2000", holdings.clone())];
+        let plan = calculate_orders(&config, &accounts, Mode::Full, Decimal::from(-2000)).unwrap();
+        assert_eq!(
+            summary(&plan.orders),
+            vec![
+                ("main", "ABC123", "SELL", Decimal::from(1000)),
+                ("main", "ABC456", "SELL", Decimal::from(400)),
+                ("main", "ABC789", "SELL", Decimal::from(600)),
+            ]
+        );
+
+        let error =
+            calculate_orders(&config, &accounts, Mode::BuyOnly, Decimal::from(-2000)).unwrap_err();
+        assert!(error.to_string().contains("needs $2000.00 of sales"));
+
+        // Withdrawing more than the account holds is an error in every mode
+        let accounts = [account("main", "-12500", holdings)];
+        for mode in [Mode::Full, Mode::BuyOnly, Mode::SellOnly] {
+            let error =
+                calculate_orders(&config, &accounts, mode, Decimal::from(-12500)).unwrap_err();
+            assert_eq!(
+                error.to_string(),
+                "The withdrawal from account main is $2500.00 more than its cash and holdings"
+            );
+        }
+    }
+
+    #[test]
+    fn test_round_to_cents() {
+        let order = |account: &str, fund: &str, action: &str, amount: &str, currency: &str| Order {
+            account: account.to_string(),
+            fund: fund.to_string(),
+            action: action.to_string(),
+            amount: Decimal::from_str(amount).unwrap(),
+            currency: currency.to_string(),
+            quantity: None,
+            schedule: None,
+        };
+        let mut orders = vec![
+            order("rrsp", "AAA", "BUY", "10.005", "CAD"),
+            order("rrsp", "BBB", "BUY", "20.005", "CAD"),
+            order("rrsp", "CCC", "SELL", "30.003", "CAD"),
+            order("rrsp", "VTI", "BUY", "0.004", "USD"),
+            order("tfsa", "AAA", "BUY", "5.555", "CAD"),
+        ];
+        let planned = planned_cash(&orders);
+        round_to_cents(&mut orders, &planned).unwrap();
+
+        // Rounding each amount on its own would use 10.01 + 20.01 - 30.00 =
+        // 0.02 of the rrsp's CAD, not the planned 0.007 rounded to 0.01. The
+        // two cents left after rounding down go to the sell, which lost the
+        // most, then to the first of the two tied buys. The tiny USD order
+        // rounds to nothing and is dropped.
+        let cents = |amount: &str| Decimal::from_str(amount).unwrap();
+        assert_eq!(
+            summary(&orders),
+            vec![
+                ("rrsp", "AAA", "BUY", cents("10.01")),
+                ("rrsp", "BBB", "BUY", cents("20.00")),
+                ("rrsp", "CCC", "SELL", cents("30.00")),
+                ("tfsa", "AAA", "BUY", cents("5.56")),
+            ]
+        );
+
+        // Amounts that drifted from the plan cannot be rounded to it
+        let mut orders = vec![
+            order("rrsp", "AAA", "BUY", "10.005", "CAD"),
+            order("rrsp", "BBB", "BUY", "20.005", "CAD"),
+        ];
+        let planned = planned_cash(&orders);
+        orders[1].amount = Decimal::from_str("25.005").unwrap();
+        let error = round_to_cents(&mut orders, &planned).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Orders for account rrsp use 35.00 CAD of cash, but 30.01 was planned"
+        );
+    }
+
+    #[test]
+    fn test_sequence_orders() {
+        let order = |account: &str, fund: &str, action: &str, amount: i64| Order {
+            account: account.to_string(),
+            fund: fund.to_string(),
+            action: action.to_string(),
+            amount: Decimal::from(amount),
+            currency: "CAD".to_string(),
+            quantity: None,
+            schedule: None,
+        };
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("fx.csv");
+        std::fs::write(&path, "Currency,Rate\nUSD,1.25\n").unwrap();
+        let fx = FxRates::load(&path, "CAD").unwrap();
+        let orders = || {
+            vec![
+                order("tfsa", "AAA", "BUY", 100),
+                order("rrsp", "CCC", "BUY", 300),
+                order("rrsp", "OTHER", "SELL", 50),
+                order("rrsp", "AAA", "BUY", 200),
+                order("rrsp", "BBB", "SELL", 400),
+                order("rrsp", "DDD", "BUY", 300),
+                // 312.50 in the base currency
+                Order {
+                    currency: "USD".to_string(),
+                    ..order("rrsp", "VTI", "BUY", 250)
+                },
+            ]
+        };
+        let sequence = |sort_by: SortBy| {
+            let mut orders = orders();
+            sequence_orders(&mut orders, sort_by, &["DDD", "CCC", "BBB", "AAA"], &fx).unwrap();
+            orders
+                .iter()
+                .map(|o| format!("{} {} {}", o.account, o.action, o.fund))
+                .collect::<Vec<_>>()
+        };
+
+        // Accounts stay in the order given, sells come first, and funds
+        // without a position in the config come last
+        assert_eq!(
+            sequence(SortBy::Config),
+            vec![
+                "tfsa BUY AAA",
+                "rrsp SELL BBB",
+                "rrsp SELL OTHER",
+                "rrsp BUY DDD",
+                "rrsp BUY CCC",
+                "rrsp BUY AAA",
+                "rrsp BUY VTI",
+            ]
+        );
+        // Amounts are compared in the base currency, so the USD 250 of VTI
+        // comes first, and equal amounts fall back to the symbol
+        assert_eq!(
+            sequence(SortBy::Amount),
+            vec![
+                "tfsa BUY AAA",
+                "rrsp SELL BBB",
+                "rrsp SELL OTHER",
+                "rrsp BUY VTI",
+                "rrsp BUY CCC",
+                "rrsp BUY DDD",
+                "rrsp BUY AAA",
+            ]
+        );
+        assert_eq!(
+            sequence(SortBy::Symbol),
+            vec![
+                "tfsa BUY AAA",
+                "rrsp SELL BBB",
+                "rrsp SELL OTHER",
+                "rrsp BUY AAA",
+                "rrsp BUY CCC",
+                "rrsp BUY DDD",
+                "rrsp BUY VTI",
+            ]
+        );
+    }
+
+    fn summary(orders: &[Order]) -> Vec<(&str, &str, &str, Decimal)> {
+        orders
+            .iter()
+            .map(|o| {
+                (
+                    o.account.as_str(),
+                    o.fund.as_str(),
+                    o.action.as_str(),
+                    o.amount,
+                )
+            })
+            .collect()
+    }
+
+    fn bonds_in_rrsp(funds: IndexMap<String, Decimal>) -> Config {
+        Config {
+            funds,
+            asset_location: vec![LocationRule {
+                class: "bonds".to_string(),
+                funds: vec!["BONDS".to_string()],
+                accounts: vec!["rrsp".to_string()],
+            }],
+            ..Default::default()
+        }
+    }
+
+    fn typed_account(id: &str, kind: &str, cash: &str, holdings: Vec<Holding>) -> Account {
+        Account {
+            kind: Some(kind.to_string()),
+            ..account(id, cash, holdings)
+        }
+    }
+
+    #[test]
+    fn test_asset_location_moves_funds_between_accounts() {
+        let config = bonds_in_rrsp(IndexMap::from([
+            ("EQUITY".to_string(), Decimal::from(50)),
+            ("BONDS".to_string(), Decimal::from(50)),
+        ]));
+        let accounts = [
+            typed_account("retirement", "RRSP", "0", vec![holding("EQUITY", "5000")]),
+            typed_account("brokerage", "taxable", "0", vec![holding("BONDS", "5000")]),
+        ];
+
+        let plan = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO).unwrap();
+
+        // Already balanced as a household, but the bonds belong in the rrsp
+        assert!(plan.shortfalls.is_empty());
+        assert_eq!(
+            summary(&plan.orders),
+            vec![
+                ("retirement", "BONDS", "BUY", Decimal::from(5000)),
+                ("retirement", "EQUITY", "SELL", Decimal::from(5000)),
+                ("brokerage", "BONDS", "SELL", Decimal::from(5000)),
+                ("brokerage", "EQUITY", "BUY", Decimal::from(5000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_asset_location_reports_shortfalls() {
+        let config = bonds_in_rrsp(IndexMap::from([
+            ("EQUITY".to_string(), Decimal::from(50)),
+            ("BONDS".to_string(), Decimal::from(50)),
+        ]));
+        let accounts = [
+            typed_account("retirement", "rrsp", "0", vec![holding("EQUITY", "3000")]),
+            typed_account("brokerage", "taxable", "0", vec![holding("BONDS", "7000")]),
+        ];
+
+        let plan = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO).unwrap();
+
+        // The rrsp only has room for 3000 of the 5000 bond target
+        assert_eq!(
+            plan.shortfalls,
+            vec![Shortfall::NotPreferred {
+                fund: "BONDS".to_string(),
+                class: "bonds".to_string(),
+                accounts: vec!["rrsp".to_string()],
+                amount: Decimal::from(2000),
+            }]
+        );
+        assert_eq!(
+            plan.shortfalls[0].to_string(),
+            "$2000.00 of BONDS (bonds) does not fit in rrsp accounts and is held elsewhere"
+        );
+        assert_eq!(
+            summary(&plan.orders),
+            vec![
+                ("retirement", "BONDS", "BUY", Decimal::from(3000)),
+                ("retirement", "EQUITY", "SELL", Decimal::from(3000)),
+                ("brokerage", "BONDS", "SELL", Decimal::from(5000)),
+                ("brokerage", "EQUITY", "BUY", Decimal::from(5000)),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_unmanaged_holdings() {
+        let mut config = bonds_in_rrsp(IndexMap::from([
+            ("EQUITY".to_string(), Decimal::from(50)),
+            ("BONDS".to_string(), Decimal::from(50)),
+        ]));
+        let accounts = [account(
+            "main",
+            "1000",
+            vec![holding("EQUITY", "1000"), holding("OTHER", "8000")],
+        )];
+
+        // By default, they are ignored and not part of the 2000 portfolio
+        assert_eq!(config.targets.unmanaged_holdings, Unmanaged::Ignore);
+        let plan = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO).unwrap();
+        assert_eq!(
+            plan.unmanaged,
+            vec![UnmanagedHolding {
+                account: "main".to_string(),
+                fund: "OTHER".to_string(),
+                value: Decimal::from(8000),
+                liquidated: false,
+            }]
+        );
+        assert_eq!(
+            summary(&plan.orders),
+            vec![("main", "BONDS", "BUY", Decimal::from(1000))]
+        );
+
+        config.targets.unmanaged_holdings = Unmanaged::Error;
+        let error = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("Holdings not in the config: OTHER ($8000.00 in account main)."));
+
+        // Liquidated holdings are sold and their proceeds rebalanced
+        config.targets.unmanaged_holdings = Unmanaged::Liquidate;
+        let plan = calculate_orders(&config, &accounts, Mode::Full, Decimal::ZERO).unwrap();
+        assert!(plan.unmanaged[0].liquidated);
+        assert_eq!(
+            summary(&plan.orders),
+            vec![
+                ("main", "OTHER", "SELL", Decimal::from(8000)),
+                ("main", "BONDS", "BUY", Decimal::from(5000)),
+                ("main", "EQUITY", "BUY", Decimal::from(4000)),
+            ]
+        );
+
+        // except in buy-only mode, which never sells
+        let plan = calculate_orders(&config, &accounts, Mode::BuyOnly, Decimal::ZERO).unwrap();
+        assert!(!plan.unmanaged[0].liquidated);
+        assert_eq!(
+            summary(&plan.orders),
+            vec![("main", "BONDS", "BUY", Decimal::from(1000))]
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::Result;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::fmt;
+
+use crate::fx::{CurrencyConfig, FxRates};
+use crate::statement::Statement;
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct ReconciliationConfig {
+    /// Largest difference between a reported and a parsed total that is
+    /// still accepted, to allow for per-row rounding in the statement
+    pub tolerance: Decimal,
+}
+
+impl Default for ReconciliationConfig {
+    fn default() -> Self {
+        ReconciliationConfig {
+            tolerance: Decimal::new(1, 2),
+        }
+    }
+}
+
+/// A statement total that does not match the sum of the parsed rows.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mismatch {
+    pub total: &'static str,
+    pub reported: Decimal,
+    pub parsed: Decimal,
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "statement reports {} of ${:.2} but the parsed rows add up to ${:.2} (off by ${:.2})",
+            self.total,
+            self.reported,
+            self.parsed,
+            self.parsed - self.reported
+        )
+    }
+}
+
+/// Compares the statement's own `Investments` and `Total Value` against the
+/// parsed market values and cash. Totals the statement does not report are
+/// not checked.
+///
+/// The totals are taken to be in the statement's cash currency (or the base
+/// currency), so holdings in other currencies are converted to it first.
+pub fn reconcile(
+    statement: &Statement,
+    config: &ReconciliationConfig,
+    currency: &CurrencyConfig,
+    fx: &FxRates,
+) -> Result<Vec<Mismatch>> {
+    let statement_currency = statement.cash_currency.as_deref().unwrap_or(fx.base());
+    let mut investments = Decimal::ZERO;
+    for holding in &statement.holdings {
+        let holding_currency = currency.trading_currency(&holding.symbol, &statement.holdings);
+        investments += if holding_currency.eq_ignore_ascii_case(statement_currency) {
+            holding.market_value
+        } else {
+            let value = fx.to_base(holding.market_value, holding_currency)?;
+            fx.to_currency(value, statement_currency)?
+        };
+    }
+    let checks = [
+        ("Investments", statement.investments, investments),
+        (
+            "Total Value",
+            statement.total_value,
+            investments + statement.cash,
+        ),
+    ];
+
+    Ok(checks
+        .into_iter()
+        .filter_map(|(total, reported, parsed)| {
+            let reported = reported?;
+            ((parsed - reported).abs() > config.tolerance).then_some(Mismatch {
+                total,
+                reported,
+                parsed,
+            })
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statement::Holding;
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    fn check(statement: &Statement, config: &ReconciliationConfig) -> Vec<Mismatch> {
+        reconcile(
+            statement,
+            config,
+            &CurrencyConfig::default(),
+            &FxRates::new("CAD"),
+        )
+        .unwrap()
+    }
+
+    fn statement(investments: &str, total_value: &str) -> Statement {
+        Statement {
+            cash: Decimal::from(1000),
+            investments: Some(Decimal::from_str(investments).unwrap()),
+            total_value: Some(Decimal::from_str(total_value).unwrap()),
+            holdings: vec![
+                Holding {
+                    symbol: "ABC123".to_string(),
+                    market_value: Decimal::from_str("6000.00").unwrap(),
+                    ..Default::default()
+                },
+                Holding {
+                    symbol: "ABC456".to_string(),
+                    market_value: Decimal::from_str("3000.00").unwrap(),
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_reconcile_within_tolerance() {
+        let config = ReconciliationConfig::default();
+
+        assert!(check(&statement("9000.00", "10000.00"), &config).is_empty());
+        assert!(check(&statement("9000.01", "9999.99"), &config).is_empty());
+    }
+
+    #[test]
+    fn test_reconcile_reports_mismatches() {
+        let config = ReconciliationConfig::default();
+
+        // A row worth 3000.00 is missing from the parsed holdings
+        let mismatches = check(&statement("12000.00", "13000.00"), &config);
+        assert_eq!(
+            mismatches,
+            vec![
+                Mismatch {
+                    total: "Investments",
+                    reported: Decimal::from_str("12000.00").unwrap(),
+                    parsed: Decimal::from_str("9000.00").unwrap(),
+                },
+                Mismatch {
+                    total: "Total Value",
+                    reported: Decimal::from_str("13000.00").unwrap(),
+                    parsed: Decimal::from_str("10000.00").unwrap(),
+                },
+            ]
+        );
+
+        let loose = ReconciliationConfig {
+            tolerance: Decimal::from(5000),
+        };
+        assert!(check(&statement("12000.00", "13000.00"), &loose).is_empty());
+    }
+
+    #[test]
+    fn test_reconcile_skips_unreported_totals() {
+        let mut statement = statement("0", "0");
+        statement.investments = None;
+        statement.total_value = None;
+
+        assert!(check(&statement, &ReconciliationConfig::default()).is_empty());
+    }
+
+    #[test]
+    fn test_reconcile_converts_currencies() {
+        // The statement reports its totals in USD, and holds a CAD fund
+        let mut statement = statement("8800.00", "9800.00");
+        statement.cash_currency = Some("USD".to_string());
+        statement.holdings[0].currency = Some("USD".to_string());
+        let currency = CurrencyConfig {
+            funds: HashMap::from([("ABC456".to_string(), "CAD".to_string())]),
+            ..Default::default()
+        };
+        let temp_dir = tempfile::TempDir::new().unwrap();
+        let path = temp_dir.path().join("fx.csv");
+        std::fs::write(&path, "Currency,Rate\nUSD,1.25\n").unwrap();
+        let fx = FxRates::load(&path, "CAD").unwrap();
+        let config = ReconciliationConfig::default();
+
+        // 6000 USD + 3000 CAD (2400 USD) is 8400 USD, not 9000
+        let mismatches = reconcile(&statement, &config, &currency, &fx).unwrap();
+        assert_eq!(mismatches[0].parsed, Decimal::from(8400));
+
+        let statement = Statement {
+            investments: Some(Decimal::from(8400)),
+            total_value: Some(Decimal::from(9400)),
+            ..statement
+        };
+        assert!(reconcile(&statement, &config, &currency, &fx)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,290 @@
+use anyhow::{bail, Result};
+use chrono::{Datelike, Duration, NaiveDate, Weekday};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::fx::FxRates;
+use crate::rebalance::Order;
+
+/// How many business days trades take to settle.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct SettlementConfig {
+    /// Business days after the trade date that a trade settles
+    pub days: u32,
+    /// Settlement days per fund, overriding `days`
+    pub funds: HashMap<String, u32>,
+}
+
+impl Default for SettlementConfig {
+    fn default() -> Self {
+        SettlementConfig {
+            days: 2,
+            funds: HashMap::new(),
+        }
+    }
+}
+
+impl SettlementConfig {
+    /// The date a trade of `fund` placed on `trade_date` settles.
+    fn settles(&self, fund: &str, trade_date: NaiveDate) -> NaiveDate {
+        let days = self.funds.get(fund).copied().unwrap_or(self.days);
+        let mut date = trade_date;
+        for _ in 0..days {
+            date = next_business_day(date + Duration::days(1));
+        }
+        date
+    }
+}
+
+/// When an order is placed and when it settles. Phase 1 is placed on the
+/// trade date; phase 2 buys wait for the sales that pay for them to settle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Schedule {
+    pub phase: u8,
+    pub trade_date: NaiveDate,
+    pub settlement_date: NaiveDate,
+}
+
+/// `date`, or the Monday after it if it falls on a weekend.
+pub fn next_business_day(date: NaiveDate) -> NaiveDate {
+    match date.weekday() {
+        Weekday::Sat => date + Duration::days(2),
+        Weekday::Sun => date + Duration::days(1),
+        _ => date,
+    }
+}
+
+/// Splits each account's orders into two phases, since a buy cannot be paid
+/// for with the proceeds of a sale that has not settled.
+///
+/// Phase 1 holds the sells, plus the buys the account's `cash` (in the base
+/// currency) covers, taken in order. The remaining buys are phase 2, each
+/// placed on the date enough sales have settled to pay for it. Orders are
+/// then moved so that each account's phase 1 comes before its phase 2.
+///
+/// It is an error for a buy to cost more than the account's cash and all of
+/// its sales provide, beyond the cent that rounding may leave short.
+pub fn schedule_orders(
+    orders: &mut [Order],
+    cash: &HashMap<String, Decimal>,
+    settlement: &SettlementConfig,
+    trade_date: NaiveDate,
+    fx: &FxRates,
+) -> Result<()> {
+    let trade_date = next_business_day(trade_date);
+    let mut accounts: Vec<String> = Vec::new();
+    for order in orders.iter() {
+        if !accounts.contains(&order.account) {
+            accounts.push(order.account.clone());
+        }
+    }
+
+    for account in &accounts {
+        let indexes: Vec<usize> = (0..orders.len())
+            .filter(|&i| &orders[i].account == account)
+            .collect();
+        let mut available = cash
+            .get(account)
+            .copied()
+            .unwrap_or_default()
+            .max(Decimal::ZERO);
+
+        let mut proceeds = Vec::new();
+        for &i in &indexes {
+            let order = &mut orders[i];
+            if order.action != "SELL" {
+                continue;
+            }
+            let settlement_date = settlement.settles(&order.fund, trade_date);
+            order.schedule = Some(Schedule {
+                phase: 1,
+                trade_date,
+                settlement_date,
+            });
+            proceeds.push((settlement_date, fx.to_base(order.amount, &order.currency)?));
+        }
+        proceeds.sort();
+
+        let mut waiting = Vec::new();
+        for &i in &indexes {
+            let order = &mut orders[i];
+            if order.action != "BUY" {
+                continue;
+            }
+            let cost = fx.to_base(order.amount, &order.currency)?;
+            if cost > available {
+                waiting.push((i, cost));
+                continue;
+            }
+            available -= cost;
+            order.schedule = Some(Schedule {
+                phase: 1,
+                trade_date,
+                settlement_date: settlement.settles(&order.fund, trade_date),
+            });
+        }
+
+        let cent = Decimal::new(1, 2);
+        let mut proceeds = proceeds.into_iter();
+        let mut date = trade_date;
+        let mut settled = false;
+        for (i, cost) in waiting {
+            while available < cost {
+                let Some((settlement_date, amount)) = proceeds.next() else {
+                    break;
+                };
+                available += amount;
+                date = date.max(settlement_date);
+                settled = true;
+            }
+            if cost - available >= cent {
+                let order = &orders[i];
+                bail!(
+                    "Account {} cannot pay for the {} buy of {}: it is short {:.2} in the \
+                     base currency after its cash and all of its sales",
+                    account,
+                    order.fund,
+                    order.amount,
+                    cost - available
+                );
+            }
+            available -= cost;
+            let order = &mut orders[i];
+            order.schedule = Some(Schedule {
+                phase: if settled { 2 } else { 1 },
+                trade_date: date,
+                settlement_date: settlement.settles(&order.fund, date),
+            });
+        }
+    }
+
+    orders.sort_by_key(|order| {
+        (
+            accounts.iter().position(|id| *id == order.account),
+            order.schedule.map(|s| s.phase),
+        )
+    });
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn date(text: &str) -> NaiveDate {
+        NaiveDate::parse_from_str(text, "%Y-%m-%d").unwrap()
+    }
+
+    fn order(account: &str, fund: &str, action: &str, amount: i64) -> Order {
+        Order {
+            account: account.to_string(),
+            fund: fund.to_string(),
+            action: action.to_string(),
+            amount: Decimal::from(amount),
+            currency: "CAD".to_string(),
+            quantity: None,
+            schedule: None,
+        }
+    }
+
+    #[test]
+    fn test_settles() {
+        let settlement: SettlementConfig =
+            toml::from_str("days = 2\n[funds]\nVTSAX = 1\nVFV = 0\n").unwrap();
+        // Thursday T+2 skips the weekend
+        assert_eq!(
+            settlement.settles("VBTLX", date("2025-05-22")),
+            date("2025-05-26")
+        );
+        assert_eq!(
+            settlement.settles("VTSAX", date("2025-05-22")),
+            date("2025-05-23")
+        );
+        assert_eq!(
+            settlement.settles("VFV", date("2025-05-22")),
+            date("2025-05-22")
+        );
+        assert_eq!(SettlementConfig::default().days, 2);
+        assert_eq!(next_business_day(date("2025-05-24")), date("2025-05-26"));
+    }
+
+    #[test]
+    fn test_schedule_orders() {
+        let settlement: SettlementConfig = toml::from_str("[funds]\nBBB = 1\n").unwrap();
+        let mut orders = vec![
+            order("rrsp", "AAA", "SELL", 3000),
+            order("rrsp", "BBB", "SELL", 1000),
+            order("rrsp", "DDD", "BUY", 1000),
+            order("rrsp", "CCC", "BUY", 1100),
+            order("rrsp", "EEE", "BUY", 1200),
+            order("tfsa", "AAA", "BUY", 500),
+        ];
+        let cash = HashMap::from([
+            ("rrsp".to_string(), Decimal::from(1200)),
+            ("tfsa".to_string(), Decimal::from(500)),
+        ]);
+        // Placed on a Saturday, so trading starts on Monday
+        schedule_orders(
+            &mut orders,
+            &cash,
+            &settlement,
+            date("2025-05-24"),
+            &FxRates::new("CAD"),
+        )
+        .unwrap();
+
+        // The 1200 of cash pays for DDD, leaving 200; CCC waits for BBB's
+        // proceeds on Tuesday, and EEE for AAA's on Wednesday
+        let schedule = |phase, trade: &str, settles: &str| {
+            Some(Schedule {
+                phase,
+                trade_date: date(trade),
+                settlement_date: date(settles),
+            })
+        };
+        let summary: Vec<_> = orders
+            .iter()
+            .map(|o| (o.fund.as_str(), o.schedule))
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                ("AAA", schedule(1, "2025-05-26", "2025-05-28")),
+                ("BBB", schedule(1, "2025-05-26", "2025-05-27")),
+                ("DDD", schedule(1, "2025-05-26", "2025-05-28")),
+                ("CCC", schedule(2, "2025-05-27", "2025-05-29")),
+                ("EEE", schedule(2, "2025-05-28", "2025-05-30")),
+                ("AAA", schedule(1, "2025-05-26", "2025-05-28")),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_schedule_orders_unfunded() {
+        let settlement = SettlementConfig::default();
+        let fx = FxRates::new("CAD");
+        let cash = HashMap::from([("rrsp".to_string(), Decimal::from(500))]);
+
+        // No sells at all to pay for the rest of the buy
+        let mut orders = vec![order("rrsp", "AAA", "BUY", 800)];
+        let error = schedule_orders(&mut orders, &cash, &settlement, date("2025-05-26"), &fx)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("Account rrsp cannot pay for the AAA buy of 800"));
+        assert!(error.contains("short 300.00"));
+
+        // CCC takes the cash, and the sale only pays for part of BBB
+        let mut orders = vec![
+            order("rrsp", "AAA", "SELL", 400),
+            order("rrsp", "BBB", "BUY", 600),
+            order("rrsp", "CCC", "BUY", 500),
+        ];
+        let error = schedule_orders(&mut orders, &cash, &settlement, date("2025-05-26"), &fx)
+            .unwrap_err()
+            .to_string();
+        assert!(error.contains("the BBB buy of 600"));
+        assert!(error.contains("short 200.00"));
+    }
+}
@@ -0,0 +1,409 @@
+use anyhow::Result;
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use crate::fx::FxRates;
+use crate::rebalance::Order;
+use crate::statement::Holding;
+
+/// Decimal places of the share estimate shown for dollar-based orders.
+const ESTIMATE_PLACES: u32 = 4;
+
+/// How a fund's orders are sized, set per fund in `[shares]` as `"whole"`,
+/// `"fractional:N"` or `"dollars"`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "String")]
+pub enum Rounding {
+    /// Whole shares only
+    Whole,
+    /// Shares to this many decimal places
+    Fractional(u32),
+    /// Any dollar amount; the share quantity is only an estimate
+    #[default]
+    Dollars,
+}
+
+impl TryFrom<String> for Rounding {
+    type Error = String;
+
+    fn try_from(text: String) -> Result<Self, Self::Error> {
+        match text.split_once(':') {
+            None if text == "whole" => Ok(Rounding::Whole),
+            None if text == "dollars" => Ok(Rounding::Dollars),
+            Some(("fractional", places)) => places
+                .parse()
+                .ok()
+                .filter(|&places| places <= 9)
+                .map(Rounding::Fractional)
+                .ok_or_else(|| format!("expected fractional:N with N from 0 to 9, got '{}'", text)),
+            _ => Err(format!(
+                "expected \"whole\", \"fractional:N\" or \"dollars\", got '{}'",
+                text
+            )),
+        }
+    }
+}
+
+impl Rounding {
+    /// Smallest quantity that can be ordered, or `None` for dollar amounts.
+    fn step(self) -> Option<Decimal> {
+        match self {
+            Rounding::Whole => Some(Decimal::ONE),
+            Rounding::Fractional(places) => Some(Decimal::new(1, places)),
+            Rounding::Dollars => None,
+        }
+    }
+}
+
+/// An order being sized: its price, how it is rounded, and the amount the
+/// plan asked for, in the base currency.
+struct Sizing {
+    price: Option<Decimal>,
+    rounding: Rounding,
+    planned: Decimal,
+}
+
+/// Adds share quantities to the orders, from the price of each fund in the
+/// statements, and rounds the orders of funds traded in shares.
+///
+/// Buys are rounded down and sells up (never past the shares held), so the
+/// rounded orders never need more cash than planned. The cash left over in
+/// each account is then spent on one more share of the buys that fell
+/// furthest short of their planned amount, and whatever remains stays in
+/// cash, since adding it to another buy would push that fund past its
+/// target. Orders that round to nothing are dropped. Returns a warning for
+/// each fund traded in shares that has no price, which is ordered in dollars
+/// instead.
+pub fn size_orders(
+    orders: &mut Vec<Order>,
+    rounding: &HashMap<String, Rounding>,
+    holdings: &HashMap<String, Vec<Holding>>,
+    fx: &FxRates,
+) -> Result<Vec<String>> {
+    let mut warnings = Vec::new();
+    let mut sizings = Vec::new();
+    for order in orders.iter_mut() {
+        let held = holdings
+            .get(&order.account)
+            .and_then(|holdings| holdings.iter().find(|h| h.symbol == order.fund));
+        let price = held
+            .and_then(|h| h.price)
+            .or_else(|| {
+                holdings
+                    .values()
+                    .flatten()
+                    .find(|h| h.symbol == order.fund)
+                    .and_then(|h| h.price)
+            })
+            .filter(|price| *price > Decimal::ZERO);
+        let mut fund_rounding = rounding.get(&order.fund).copied().unwrap_or_default();
+        if price.is_none() && fund_rounding != Rounding::Dollars {
+            warnings.push(format!(
+                "No price for {} in the statement; ordering it in dollars",
+                order.fund
+            ));
+            fund_rounding = Rounding::Dollars;
+        }
+        sizings.push(Sizing {
+            price,
+            rounding: fund_rounding,
+            planned: signed(order, fx)?,
+        });
+
+        let Some(price) = price else {
+            continue;
+        };
+        let places = match fund_rounding {
+            Rounding::Whole => 0,
+            Rounding::Fractional(places) => places,
+            Rounding::Dollars => {
+                // A sale of a whole position already knows its quantity
+                let estimate = (order.amount / price).round_dp(ESTIMATE_PLACES);
+                order.quantity = order.quantity.or(Some(estimate));
+                continue;
+            }
+        };
+        let shares = order.amount / price;
+        let mut quantity = if order.action == "BUY" {
+            shares.round_dp_with_strategy(places, RoundingStrategy::ToZero)
+        } else {
+            shares.round_dp_with_strategy(places, RoundingStrategy::AwayFromZero)
+        };
+        if order.action == "SELL" {
+            if let Some(held) = held.and_then(|h| h.quantity) {
+                quantity = quantity.min(held);
+            }
+        }
+        set_quantity(order, quantity, price);
+    }
+
+    let mut accounts: Vec<&str> = Vec::new();
+    for order in orders.iter() {
+        if !accounts.contains(&order.account.as_str()) {
+            accounts.push(&order.account);
+        }
+    }
+    let accounts: Vec<String> = accounts.into_iter().map(String::from).collect();
+    for account in &accounts {
+        let indexes: Vec<usize> = (0..orders.len())
+            .filter(|&index| &orders[index].account == account)
+            .collect();
+        redistribute(orders, &sizings, &indexes, fx)?;
+    }
+
+    orders.retain(|order| order.amount > Decimal::ZERO);
+    Ok(warnings)
+}
+
+/// Spends an account's cash left over from rounding, or takes back cash
+/// that rounding overspent.
+fn redistribute(
+    orders: &mut [Order],
+    sizings: &[Sizing],
+    indexes: &[usize],
+    fx: &FxRates,
+) -> Result<()> {
+    let mut leftover = Decimal::ZERO;
+    for &index in indexes {
+        leftover += sizings[index].planned - signed(&orders[index], fx)?;
+    }
+
+    // One more share for the buys furthest short of their plan
+    loop {
+        let mut best: Option<(usize, Decimal, Decimal)> = None;
+        for &index in indexes {
+            let (order, sizing) = (&orders[index], &sizings[index]);
+            let (Some(step), Some(price)) = (sizing.rounding.step(), sizing.price) else {
+                continue;
+            };
+            if order.action != "BUY" {
+                continue;
+            }
+            let short = sizing.planned - signed(order, fx)?;
+            let cost = fx.to_base(step * price, &order.currency)?;
+            if short > Decimal::ZERO
+                && cost <= leftover
+                && best.is_none_or(|(_, best_short, _)| short > best_short)
+            {
+                best = Some((index, short, cost));
+            }
+        }
+        let Some((index, _, cost)) = best else {
+            break;
+        };
+        let (step, price) = (sizings[index].rounding.step(), sizings[index].price);
+        let quantity = orders[index].quantity.unwrap_or_default() + step.unwrap_or_default();
+        set_quantity(&mut orders[index], quantity, price.unwrap_or_default());
+        leftover -= cost;
+    }
+
+    // Capping a sell at the shares held can leave too little cash for the
+    // buys, so take it back from the dollar-based buys, largest first
+    let mut dollar_buys: Vec<usize> = indexes
+        .iter()
+        .copied()
+        .filter(|&index| {
+            orders[index].action == "BUY" && sizings[index].rounding == Rounding::Dollars
+        })
+        .collect();
+    dollar_buys.sort_by_key(|&index| std::cmp::Reverse(orders[index].amount));
+    for index in dollar_buys {
+        if leftover >= Decimal::ZERO {
+            break;
+        }
+        let order = &mut orders[index];
+        let trim = fx.to_base(order.amount, &order.currency)?.min(-leftover);
+        order.amount = (order.amount - fx.to_currency(trim, &order.currency)?).max(Decimal::ZERO);
+        if let Some(price) = sizings[index].price {
+            order.quantity = Some((order.amount / price).round_dp(ESTIMATE_PLACES));
+        }
+        leftover += trim;
+    }
+
+    // Then give back shares of the largest buys until they fit
+    while leftover < Decimal::ZERO {
+        let largest = indexes
+            .iter()
+            .copied()
+            .filter(|&index| {
+                orders[index].action == "BUY"
+                    && orders[index].quantity.is_some_and(|q| q > Decimal::ZERO)
+                    && sizings[index].rounding.step().is_some()
+            })
+            .max_by_key(|&index| orders[index].amount);
+        let Some(index) = largest else {
+            break;
+        };
+        let step = sizings[index].rounding.step().unwrap_or_default();
+        let price = sizings[index].price.unwrap_or_default();
+        let quantity = orders[index].quantity.unwrap_or_default() - step;
+        set_quantity(&mut orders[index], quantity, price);
+        leftover += fx.to_base(step * price, &orders[index].currency)?;
+    }
+    Ok(())
+}
+
+fn set_quantity(order: &mut Order, quantity: Decimal, price: Decimal) {
+    order.quantity = Some(quantity);
+    order.amount = quantity * price;
+}
+
+/// The cash an order uses, in the base currency: positive for buys and
+/// negative for sells.
+fn signed(order: &Order, fx: &FxRates) -> Result<Decimal> {
+    let amount = fx.to_base(order.amount, &order.currency)?;
+    Ok(if order.action == "BUY" {
+        amount
+    } else {
+        -amount
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn order(account: &str, fund: &str, action: &str, amount: &str) -> Order {
+        Order {
+            account: account.to_string(),
+            fund: fund.to_string(),
+            action: action.to_string(),
+            amount: Decimal::from_str(amount).unwrap(),
+            currency: "CAD".to_string(),
+            quantity: None,
+            schedule: None,
+        }
+    }
+
+    fn holding(symbol: &str, quantity: &str, price: &str) -> Holding {
+        Holding {
+            symbol: symbol.to_string(),
+            quantity: Some(Decimal::from_str(quantity).unwrap()),
+            price: Some(Decimal::from_str(price).unwrap()),
+            ..Default::default()
+        }
+    }
+
+    fn summary(orders: &[Order]) -> Vec<(&str, String, Option<String>)> {
+        orders
+            .iter()
+            .map(|o| {
+                (
+                    o.fund.as_str(),
+                    o.amount.normalize().to_string(),
+                    o.quantity.map(|q| q.normalize().to_string()),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_rounding_from_config() {
+        let shares: HashMap<String, Rounding> =
+            toml::from_str("VTI = \"whole\"\nXEQT = \"fractional:3\"\nVTSAX = \"dollars\"\n")
+                .unwrap();
+        assert_eq!(shares["VTI"], Rounding::Whole);
+        assert_eq!(shares["XEQT"], Rounding::Fractional(3));
+        assert_eq!(shares["VTSAX"], Rounding::Dollars);
+
+        let error = toml::from_str::<HashMap<String, Rounding>>("VTI = \"shares\"\n").unwrap_err();
+        assert!(error.to_string().contains("expected \"whole\""));
+        assert!(toml::from_str::<HashMap<String, Rounding>>("VTI = \"fractional:x\"\n").is_err());
+    }
+
+    #[test]
+    fn test_size_orders() {
+        let rounding = HashMap::from([
+            ("VTI".to_string(), Rounding::Whole),
+            ("XEQT".to_string(), Rounding::Fractional(1)),
+            ("BND".to_string(), Rounding::Whole),
+        ]);
+        let holdings = HashMap::from([(
+            "main".to_string(),
+            vec![
+                holding("VTI", "10", "100"),
+                holding("XEQT", "10", "30"),
+                holding("BND", "10", "70"),
+                holding("VTSAX", "10", "50"),
+            ],
+        )]);
+        let fx = FxRates::new("CAD");
+
+        // Sells 250 of BND (3.57 shares, rounded up to 4 = 280) to buy 250 of
+        // VTI (2.5 shares, rounded down to 2 = 200); 80 is left over
+        let mut orders = vec![
+            order("main", "BND", "SELL", "250"),
+            order("main", "VTI", "BUY", "250"),
+        ];
+        let warnings = size_orders(&mut orders, &rounding, &holdings, &fx).unwrap();
+        assert!(warnings.is_empty());
+        // Not enough for another VTI share, so the 80 stays in cash
+        assert_eq!(
+            summary(&orders),
+            vec![
+                ("BND", "280".to_string(), Some("4".to_string())),
+                ("VTI", "200".to_string(), Some("2".to_string())),
+            ]
+        );
+
+        // The leftover buys one more share of the fractional XEQT, and the
+        // rest stays in cash rather than pushing VTSAX past its target
+        let mut orders = vec![
+            order("main", "BND", "SELL", "250"),
+            order("main", "VTI", "BUY", "150"),
+            order("main", "XEQT", "BUY", "50"),
+            order("main", "VTSAX", "BUY", "50"),
+        ];
+        size_orders(&mut orders, &rounding, &holdings, &fx).unwrap();
+        assert_eq!(
+            summary(&orders),
+            vec![
+                ("BND", "280".to_string(), Some("4".to_string())),
+                ("VTI", "100".to_string(), Some("1".to_string())),
+                ("XEQT", "51".to_string(), Some("1.7".to_string())),
+                ("VTSAX", "50".to_string(), Some("1".to_string())),
+            ]
+        );
+
+        // A sell capped at the shares held is made up for by trimming the
+        // dollar-based buys, largest first
+        let mut orders = vec![
+            order("main", "BND", "SELL", "800"),
+            order("main", "VTSAX", "BUY", "500"),
+            order("main", "NEW", "BUY", "300"),
+        ];
+        size_orders(&mut orders, &rounding, &holdings, &fx).unwrap();
+        assert_eq!(
+            summary(&orders),
+            vec![
+                ("BND", "700".to_string(), Some("10".to_string())),
+                ("VTSAX", "400".to_string(), Some("8".to_string())),
+                ("NEW", "300".to_string(), None),
+            ]
+        );
+
+        // Sells never exceed the shares held, and buys of less than a share
+        // are dropped
+        let mut orders = vec![
+            order("main", "BND", "SELL", "800"),
+            order("main", "VTI", "BUY", "50"),
+        ];
+        size_orders(&mut orders, &rounding, &holdings, &fx).unwrap();
+        assert_eq!(
+            summary(&orders),
+            vec![("BND", "700".to_string(), Some("10".to_string()))]
+        );
+
+        // A fund traded in shares without a price is ordered in dollars
+        let mut orders = vec![order("main", "NEW", "BUY", "100")];
+        let rounding = HashMap::from([("NEW".to_string(), Rounding::Whole)]);
+        let warnings = size_orders(&mut orders, &rounding, &holdings, &fx).unwrap();
+        assert_eq!(
+            warnings,
+            vec!["No price for NEW in the statement; ordering it in dollars"]
+        );
+        assert_eq!(summary(&orders), vec![("NEW", "100".to_string(), None)]);
+    }
+}
@@ -0,0 +1,178 @@
+use calamine::{open_workbook_auto_from_rs, Data, Range, Reader};
+use chrono::Duration;
+use std::io::Cursor;
+
+use crate::csv_parser::{BankCsvParser, PositionsCsvParser, Row};
+use crate::diagnostics::{ParseError, ParseWarning};
+use crate::money::MoneyFormat;
+use crate::statement::{Statement, StatementParser};
+
+/// Zip archives (`.xlsx`, `.ods`) and OLE compound files (`.xls`).
+const SPREADSHEET_MAGIC: &[&[u8]] = &[b"PK\x03\x04", b"\xD0\xCF\x11\xE0\xA1\xB1\x1A\xE1"];
+
+/// Renders a cell the way it would appear in a CSV export. Numbers use the
+/// format's decimal separator so string and numeric cells read the same way,
+/// and dates use the `As of Date` layout.
+fn cell_text(cell: &Data, money: &MoneyFormat) -> String {
+    match cell {
+        Data::Empty => String::new(),
+        Data::String(text) => text.trim().to_string(),
+        Data::Float(value) => value
+            .to_string()
+            .replace('.', &money.decimal_separator.to_string()),
+        Data::DateTime(datetime) => datetime
+            .as_datetime()
+            // Excel stores times as fractions of a day; round to the nearest second
+            .map(|datetime| {
+                (datetime + Duration::milliseconds(500))
+                    .format("%Y-%m-%d %H:%M:%S")
+                    .to_string()
+            })
+            .unwrap_or_else(|| datetime.to_string()),
+        other => other.to_string(),
+    }
+}
+
+/// Converts a worksheet into rows, numbered by spreadsheet row. The rows
+/// start at the sheet's first used column, and fully blank rows are dropped,
+/// as in `read_rows`.
+fn sheet_rows(range: &Range<Data>, money: &MoneyFormat) -> Vec<Row> {
+    let (first_row, _) = range.start().unwrap_or_default();
+    range
+        .rows()
+        .enumerate()
+        .map(|(index, cells)| Row {
+            line: u64::from(first_row) + index as u64 + 1,
+            fields: cells.iter().map(|cell| cell_text(cell, money)).collect(),
+        })
+        .filter(|row| row.fields.iter().any(|field| !field.is_empty()))
+        .collect()
+}
+
+/// An Excel or OpenDocument spreadsheet download (`.xlsx`, `.xls`, `.ods`).
+/// The first worksheet laid out like one of the CSV formats is read with
+/// that format's header-driven logic.
+pub struct SpreadsheetParser;
+
+impl StatementParser for SpreadsheetParser {
+    fn detect(&self, data: &[u8]) -> bool {
+        SPREADSHEET_MAGIC
+            .iter()
+            .any(|magic| data.starts_with(magic))
+    }
+
+    fn parse(
+        &self,
+        data: &[u8],
+        money: &MoneyFormat,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<Statement, ParseError> {
+        let mut workbook =
+            open_workbook_auto_from_rs(Cursor::new(data)).map_err(|e| ParseError::Malformed {
+                line: None,
+                message: format!("cannot open spreadsheet: {}", e),
+            })?;
+
+        for (_, range) in workbook.worksheets() {
+            let rows = sheet_rows(&range, money);
+            if BankCsvParser::detect_rows(&rows) {
+                return BankCsvParser::parse_rows(&rows, money, warnings);
+            }
+            if PositionsCsvParser::detect_rows(&rows) {
+                return PositionsCsvParser::parse_rows(&rows, money, warnings);
+            }
+        }
+
+        Err(ParseError::MissingHeader {
+            column: "Symbol".to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::statement::parse_timestamp;
+    use calamine::{ExcelDateTime, ExcelDateTimeType};
+    use rust_decimal::Decimal;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_parse_xlsx() {
+        let data = include_bytes!("../examples/test-portfolio.xlsx");
+        assert!(SpreadsheetParser.detect(data));
+
+        let mut warnings = Vec::new();
+        let statement = SpreadsheetParser
+            .parse(data, &MoneyFormat::default(), &mut warnings)
+            .unwrap();
+
+        assert!(warnings.is_empty(), "{:?}", warnings);
+        assert_eq!(statement.as_of, parse_timestamp("2025-05-24 14:39:25"));
+        assert_eq!(
+            statement.account.as_deref(),
+            Some("EXAMPLE BANK - 123456789")
+        );
+        assert_eq!(statement.cash, Decimal::from(2500));
+        assert_eq!(
+            statement.total_value,
+            Some(Decimal::from_str("50030.5").unwrap())
+        );
+        assert_eq!(statement.holdings.len(), 3);
+        assert_eq!(statement.holdings[0].symbol, "VTSAX");
+        assert_eq!(
+            statement.holdings[0].market_value,
+            Decimal::from_str("25006.15").unwrap()
+        );
+        assert_eq!(
+            statement.holdings[2].unrealized_gain,
+            Some(Decimal::from_str("-110.91").unwrap())
+        );
+    }
+
+    #[test]
+    fn test_sheet_rows() {
+        // A positions table starting at B3, with a blank row
+        let mut range = Range::new((2, 1), (5, 2));
+        range.set_value((2, 1), Data::String("Symbol".to_string()));
+        range.set_value((2, 2), Data::String("Market Value".to_string()));
+        range.set_value((3, 1), Data::String(" CASH ".to_string()));
+        range.set_value((3, 2), Data::Int(100));
+        range.set_value((5, 1), Data::String("ABC123".to_string()));
+        range.set_value((5, 2), Data::Float(1234.5));
+
+        let european = MoneyFormat {
+            decimal_separator: ',',
+            thousands_separator: Some('.'),
+        };
+        let rows = sheet_rows(&range, &european);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0].line, 3);
+        assert_eq!(rows[0].fields, vec!["Symbol", "Market Value"]);
+        assert_eq!(rows[1].fields, vec!["CASH", "100"]);
+        assert_eq!(rows[2].line, 6);
+        assert_eq!(rows[2].fields, vec!["ABC123", "1234,5"]);
+
+        let as_of = Data::DateTime(ExcelDateTime::new(
+            45801.610706,
+            ExcelDateTimeType::DateTime,
+            false,
+        ));
+        assert_eq!(
+            cell_text(&as_of, &MoneyFormat::default()),
+            "2025-05-24 14:39:25"
+        );
+    }
+
+    #[test]
+    fn test_parse_spreadsheet_errors() {
+        let mut warnings = Vec::new();
+        let money = MoneyFormat::default();
+
+        assert!(matches!(
+            SpreadsheetParser.parse(b"PK\x03\x04 not a workbook", &money, &mut warnings),
+            Err(ParseError::Malformed { line: None, .. })
+        ));
+        assert!(!SpreadsheetParser.detect(b"Symbol,Market Value\n"));
+    }
+}
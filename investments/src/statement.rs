@@ -0,0 +1,234 @@
+use anyhow::Result;
+use chrono::{NaiveDate, NaiveDateTime, NaiveTime};
+use clap::ValueEnum;
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::csv_parser::{BankCsvParser, PositionsCsvParser};
+use crate::diagnostics::{ParseError, ParseWarning};
+use crate::json_parser::JsonParser;
+use crate::money::MoneyFormat;
+use crate::ofx_parser::OfxParser;
+use crate::spreadsheet_parser::SpreadsheetParser;
+
+/// A single position from a statement. Everything except `symbol` and
+/// `market_value` is optional, since not every format reports it.
+#[derive(Debug, Clone, Default, PartialEq)]
+#[allow(dead_code)] // position detail is kept even where the rebalancer doesn't need it
+pub struct Holding {
+    pub symbol: String,
+    pub market_value: Decimal,
+    pub market: Option<String>,
+    pub description: Option<String>,
+    pub quantity: Option<Decimal>,
+    pub price: Option<Decimal>,
+    pub average_cost: Option<Decimal>,
+    pub book_cost: Option<Decimal>,
+    pub unrealized_gain: Option<Decimal>,
+    /// Currency the position is valued and traded in
+    pub currency: Option<String>,
+}
+
+/// Everything parsed from one statement: the account metadata and totals it
+/// reports, plus the cash balance and holdings.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Statement {
+    pub as_of: Option<NaiveDateTime>,
+    pub account: Option<String>,
+    pub cash: Decimal,
+    pub cash_currency: Option<String>,
+    /// Total market value of the holdings, as reported by the statement
+    pub investments: Option<Decimal>,
+    /// Cash plus investments, as reported by the statement
+    pub total_value: Option<Decimal>,
+    pub holdings: Vec<Holding>,
+}
+
+/// Parses a statement timestamp such as `2025-05-24 14:39:25`. A bare date is
+/// taken as midnight.
+pub fn parse_timestamp(text: &str) -> Option<NaiveDateTime> {
+    const FORMATS: &[&str] = &["%Y-%m-%d %H:%M:%S", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M"];
+    FORMATS
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(text, format).ok())
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_time(NaiveTime::MIN))
+        })
+}
+
+/// A broker statement layout that can be turned into a cash balance and holdings.
+pub trait StatementParser {
+    /// Returns true if `data` looks like a statement in this parser's format.
+    fn detect(&self, data: &[u8]) -> bool;
+
+    /// Parses the statement, reading amounts as described by `money`.
+    /// Problems that can be worked around are pushed onto `warnings`.
+    fn parse(
+        &self,
+        data: &[u8],
+        money: &MoneyFormat,
+        warnings: &mut Vec<ParseWarning>,
+    ) -> Result<Statement, ParseError>;
+}
+
+/// Statement formats understood by the tool, selectable with `--format`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum StatementFormat {
+    /// Bank holdings export with `Cash,` header rows and a `Symbol` table
+    BankCsv,
+    /// Plain `Symbol,Market Value` table with an optional `CASH` row
+    PositionsCsv,
+    /// OFX/QFX investment statement download (`INVSTMTRS`)
+    Ofx,
+    /// Excel or OpenDocument spreadsheet laid out like one of the CSV formats
+    Spreadsheet,
+    /// JSON statement written by another program
+    Json,
+}
+
+impl StatementFormat {
+    pub fn parser(self) -> Box<dyn StatementParser> {
+        match self {
+            StatementFormat::BankCsv => Box::new(BankCsvParser),
+            StatementFormat::PositionsCsv => Box::new(PositionsCsvParser),
+            StatementFormat::Ofx => Box::new(OfxParser),
+            StatementFormat::Spreadsheet => Box::new(SpreadsheetParser),
+            StatementFormat::Json => Box::new(JsonParser),
+        }
+    }
+
+    /// Sniffs the statement header and returns the first format that claims it.
+    pub fn detect(data: &[u8]) -> Option<StatementFormat> {
+        StatementFormat::value_variants()
+            .iter()
+            .copied()
+            .find(|format| format.parser().detect(data))
+    }
+}
+
+impl fmt::Display for StatementFormat {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let value = self
+            .to_possible_value()
+            .expect("statement formats are never skipped");
+        f.write_str(value.get_name())
+    }
+}
+
+/// Parses `data` with the given format, or the detected one if none is given.
+/// `money_formats` overrides how amounts are written for individual formats.
+pub fn parse_statement(
+    data: &[u8],
+    format: Option<StatementFormat>,
+    money_formats: &HashMap<StatementFormat, MoneyFormat>,
+    warnings: &mut Vec<ParseWarning>,
+) -> Result<(StatementFormat, Statement)> {
+    let format = match format {
+        Some(format) => format,
+        None => StatementFormat::detect(data).ok_or_else(|| {
+            anyhow::anyhow!("Unrecognized statement format. Please specify one with --format")
+        })?,
+    };
+    let money = money_formats.get(&format).cloned().unwrap_or_default();
+    let statement = format
+        .parser()
+        .parse(data, &money, warnings)
+        .map_err(|e| anyhow::anyhow!("Failed to parse {} statement: {}", format, e))?;
+    Ok((format, statement))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect_format() {
+        let bank = b"As of Date,2025-05-24 14:39:25\nAccount,BANK\nCash,100.00\n";
+        assert_eq!(
+            StatementFormat::detect(bank),
+            Some(StatementFormat::BankCsv)
+        );
+
+        let positions = b"Symbol,Market Value\nCASH,100.00\nABC123,500.00\n";
+        assert_eq!(
+            StatementFormat::detect(positions),
+            Some(StatementFormat::PositionsCsv)
+        );
+
+        let ofx = b"OFXHEADER:100\nDATA:OFXSGML\n\n<OFX>\n<INVSTMTMSGSRSV1>\n";
+        assert_eq!(StatementFormat::detect(ofx), Some(StatementFormat::Ofx));
+
+        let xlsx = include_bytes!("../examples/test-portfolio.xlsx");
+        assert_eq!(
+            StatementFormat::detect(xlsx),
+            Some(StatementFormat::Spreadsheet)
+        );
+
+        let json = b"  {\"cash\": 100, \"holdings\": []}";
+        assert_eq!(StatementFormat::detect(json), Some(StatementFormat::Json));
+
+        assert_eq!(StatementFormat::detect(b"hello world"), None);
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        let expected = NaiveDate::from_ymd_opt(2025, 5, 24)
+            .unwrap()
+            .and_hms_opt(14, 39, 25)
+            .unwrap();
+        assert_eq!(parse_timestamp("2025-05-24 14:39:25"), Some(expected));
+        assert_eq!(parse_timestamp("2025-05-24T14:39:25"), Some(expected));
+        assert_eq!(
+            parse_timestamp("2025-05-24"),
+            Some(expected.date().and_time(NaiveTime::MIN))
+        );
+        assert_eq!(parse_timestamp("May 24"), None);
+    }
+
+    #[test]
+    fn test_parse_statement_format_override() {
+        let positions = b"Symbol,Market Value\nCASH,100.00\nABC123,500.00\n";
+        let mut warnings = Vec::new();
+        let (format, _) = parse_statement(
+            positions,
+            Some(StatementFormat::BankCsv),
+            &HashMap::new(),
+            &mut warnings,
+        )
+        .unwrap();
+        assert_eq!(format, StatementFormat::BankCsv);
+        assert_eq!(warnings, vec![ParseWarning::MissingCash]);
+
+        let mut warnings = Vec::new();
+        let (format, statement) =
+            parse_statement(positions, None, &HashMap::new(), &mut warnings).unwrap();
+        assert_eq!(format, StatementFormat::PositionsCsv);
+        assert_eq!(statement.cash, Decimal::from(100));
+        assert_eq!(statement.holdings.len(), 1);
+        assert!(warnings.is_empty());
+    }
+
+    #[test]
+    fn test_parse_statement_money_format() {
+        let positions = "Symbol,Market Value\nCASH,\"100,50\"\nABC123,\"1.234,56\"\n";
+        let money_formats = HashMap::from([(
+            StatementFormat::PositionsCsv,
+            MoneyFormat {
+                decimal_separator: ',',
+                thousands_separator: Some('.'),
+            },
+        )]);
+
+        let mut warnings = Vec::new();
+        let (_, statement) =
+            parse_statement(positions.as_bytes(), None, &money_formats, &mut warnings).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(statement.cash, Decimal::new(10050, 2));
+        assert_eq!(statement.holdings[0].market_value, Decimal::new(123456, 2));
+    }
+}
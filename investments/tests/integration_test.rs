@@ -2,6 +2,15 @@ use std::fs;
 use std::process::Command;
 use tempfile::TempDir;
 
+/// Returns the order table lines of an orders file, skipping the statement
+/// summary rows written above it.
+fn order_table(content: &str) -> Vec<&str> {
+    content
+        .lines()
+        .skip_while(|line| !line.starts_with("Symbol,"))
+        .collect()
+}
+
 #[test]
 fn test_end_to_end_workflow() {
     let temp_dir = TempDir::new().unwrap();
@@ -34,7 +43,14 @@ ABC789 = 20.0
 
     // Run the program
     let output = Command::new("cargo")
-        .args(&["run", "--", "--input", input_file.to_str().unwrap()])
+        .args([
+            "run",
+            "--",
+            "--trade-date",
+            "2025-05-26",
+            "--input",
+            input_file.to_str().unwrap(),
+        ])
         .current_dir(".")
         .output()
         .expect("Failed to execute program");
@@ -51,29 +67,28 @@ ABC789 = 20.0
 
     // Read and verify output
     let output_content = fs::read_to_string(&expected_output).unwrap();
-    let lines: Vec<&str> = output_content.lines().collect();
+    assert!(output_content.starts_with(
+        "Statement,test-portfolio.csv\nAs of Date,2025-05-24 14:39:25\nAccount,BANK NAME - ACCOUNT NUMBER\n"
+    ));
+    let lines = order_table(&output_content);
 
-    assert_eq!(lines[0], "Symbol,Action,Amount");
+    assert_eq!(
+        lines[0],
+        "Symbol,Action,Amount,Currency,Quantity,Phase,Trade Date,Settlement Date"
+    );
 
     // Total portfolio: 10000
     // Target ABC123: 5000 (current: 6000) -> SELL 1000
     // Target ABC456: 3000 (current: 3000) -> no change
     // Target ABC789: 2000 (current: 0) -> BUY 2000
 
-    assert!(
-        lines.len() >= 2,
-        "Expected at least 2 lines (header + orders)"
-    );
-
-    // Find the orders (order may vary)
-    let orders: Vec<&str> = lines[1..].to_vec();
-    let order_content = orders.join("\n");
-
-    assert!(
-        order_content.contains("ABC123,SELL,1000.00") || order_content.contains("ABC123,SELL,1000")
-    );
-    assert!(
-        order_content.contains("ABC789,BUY,2000.00") || order_content.contains("ABC789,BUY,2000")
+    // Sells come first, so their proceeds pay for the buys
+    assert_eq!(
+        lines[1..],
+        [
+            "ABC123,SELL,1000.00,CAD,16.6667,1,2025-05-26,2025-05-28",
+            "ABC789,BUY,2000.00,CAD,,2,2025-05-28,2025-05-30"
+        ]
     );
 }
 
@@ -109,9 +124,11 @@ XYZ222 = 20.0
 
     // Run the program with custom config and output
     let output = Command::new("cargo")
-        .args(&[
+        .args([
             "run",
             "--",
+            "--trade-date",
+            "2025-05-26",
             "--input",
             input_file.to_str().unwrap(),
             "--config",
@@ -134,20 +151,23 @@ XYZ222 = 20.0
 
     // Verify content
     let output_content = fs::read_to_string(&custom_output).unwrap();
-    let lines: Vec<&str> = output_content.lines().collect();
+    let lines = order_table(&output_content);
 
-    assert_eq!(lines[0], "Symbol,Action,Amount");
+    assert_eq!(
+        lines[0],
+        "Symbol,Action,Amount,Currency,Quantity,Phase,Trade Date,Settlement Date"
+    );
 
     // Total: 10000
     // Target XYZ111: 8000 (current: 10000) -> SELL 2000
     // Target XYZ222: 2000 (current: 0) -> BUY 2000
 
-    let order_content = lines[1..].join("\n");
-    assert!(
-        order_content.contains("XYZ111,SELL,2000.00") || order_content.contains("XYZ111,SELL,2000")
-    );
-    assert!(
-        order_content.contains("XYZ222,BUY,2000.00") || order_content.contains("XYZ222,BUY,2000")
+    assert_eq!(
+        lines[1..],
+        [
+            "XYZ111,SELL,2000.00,CAD,20,1,2025-05-26,2025-05-28",
+            "XYZ222,BUY,2000.00,CAD,,2,2025-05-28,2025-05-30"
+        ]
     );
 }
 
@@ -172,7 +192,7 @@ ABC123,,FUND1,100.0,50.0,60.0,5000.0,6000.0,1000.0,20.0,60.0,,,,,,,,,,,,
 
     // Run the program without config file
     let output = Command::new("cargo")
-        .args(&["run", "--", "--input", input_file.to_str().unwrap()])
+        .args(["run", "--", "--input", input_file.to_str().unwrap()])
         .current_dir(".")
         .output()
         .expect("Failed to execute program");
@@ -185,3 +205,923 @@ ABC123,,FUND1,100.0,50.0,60.0,5000.0,6000.0,1000.0,20.0,60.0,,,,,,,,,,,,
     let stderr = String::from_utf8_lossy(&output.stderr);
     assert!(stderr.contains("Config file not found") || stderr.contains("config.toml"));
 }
+
+#[test]
+fn test_strict_mode_rejects_parse_warnings() {
+    let temp_dir = TempDir::new().unwrap();
+    let temp_path = temp_dir.path();
+
+    // Market value for ABC456 cannot be parsed
+    let csv_content = r#"As of Date,2025-05-24 14:39:25
+Account,BANK NAME - ACCOUNT NUMBER
+Cash,1000.00
+Investments,9000.00
+Total Value,10000.00
+,
+Symbol,Market,Description,Quantity,Average Cost,Price,Book Cost,Market Value,Unrealized $,Unrealized %,% of Positions,Loan Value,Change Today $,Change Today %,Bid,Bid Lots,Ask,Ask Lots,Volume,Day Low,Day High,52-wk Low,52-wk High
+ABC123,,FUND1,100.0,50.0,60.0,5000.0,6000.0,1000.0,20.0,60.0,,,,,,,,,,,,
+ABC456,,FUND2,50.0,60.0,60.0,3000.0,#VALUE!,0.0,0.0,30.0,,,,,,,,,,,,
+"#;
+
+    let input_file = temp_path.join("test-strict.csv");
+    fs::write(&input_file, csv_content).unwrap();
+    fs::write(
+        temp_path.join("config.toml"),
+        "[funds]\nABC123 = \"50.0\"\nABC456 = \"50.0\"\n",
+    )
+    .unwrap();
+
+    // Without --strict the warning is reported but orders are still written
+    // (the skipped row also breaks reconciliation, so that check is skipped)
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--trade-date",
+            "2025-05-26",
+            "--input",
+            input_file.to_str().unwrap(),
+            "--skip-reconciliation",
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute program");
+
+    assert!(output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("line 9, column 'Market Value': cannot parse '#VALUE!'"));
+
+    // With --strict the same warning is fatal
+    fs::remove_file(temp_path.join("test-strict-orders.csv")).unwrap();
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--trade-date",
+            "2025-05-26",
+            "--input",
+            input_file.to_str().unwrap(),
+            "--skip-reconciliation",
+            "--strict",
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute program");
+
+    assert!(
+        !output.status.success(),
+        "Program should have failed in strict mode"
+    );
+    assert!(!temp_path.join("test-strict-orders.csv").exists());
+}
+
+#[test]
+fn test_reconciliation_mismatch_error() {
+    let temp_dir = TempDir::new().unwrap();
+    let temp_path = temp_dir.path();
+
+    // Investments reports 12000.00 but the rows only add up to 9000.00
+    let csv_content = r#"As of Date,2025-05-24 14:39:25
+Account,BANK NAME - ACCOUNT NUMBER
+Cash,1000.00
+Investments,12000.00
+Total Value,13000.00
+,
+Symbol,Market,Description,Quantity,Average Cost,Price,Book Cost,Market Value
+ABC123,,FUND1,100.0,50.0,60.0,5000.0,6000.0
+ABC456,,FUND2,50.0,60.0,60.0,3000.0,3000.0
+"#;
+
+    let input_file = temp_path.join("test-mismatch.csv");
+    fs::write(&input_file, csv_content).unwrap();
+    fs::write(
+        temp_path.join("config.toml"),
+        "[funds]\nABC123 = \"50.0\"\nABC456 = \"50.0\"\n",
+    )
+    .unwrap();
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--input", input_file.to_str().unwrap()])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute program");
+
+    assert!(
+        !output.status.success(),
+        "Program should have failed reconciliation"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("statement reports Investments of $12000.00"));
+    assert!(!temp_path.join("test-mismatch-orders.csv").exists());
+}
+
+#[test]
+fn test_stale_statement_guard() {
+    let temp_dir = TempDir::new().unwrap();
+    let temp_path = temp_dir.path();
+
+    let csv_content = r#"As of Date,2025-05-24 14:39:25
+Account,BANK NAME - ACCOUNT NUMBER
+Cash,1000.00
+Investments,9000.00
+Total Value,10000.00
+,
+Symbol,Market,Description,Quantity,Average Cost,Price,Book Cost,Market Value
+ABC123,,FUND1,100.0,50.0,60.0,5000.0,6000.0
+ABC456,,FUND2,50.0,60.0,60.0,3000.0,3000.0
+"#;
+
+    let input_file = temp_path.join("test-stale.csv");
+    fs::write(&input_file, csv_content).unwrap();
+    fs::write(
+        temp_path.join("config.toml"),
+        "[funds]\nABC123 = \"50.0\"\nABC456 = \"50.0\"\n\n[freshness]\nmax_statement_age = 3\n",
+    )
+    .unwrap();
+
+    let output = Command::new("cargo")
+        .args(["run", "--", "--input", input_file.to_str().unwrap()])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute program");
+
+    assert!(
+        !output.status.success(),
+        "Program should have refused a stale statement"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("older than max_statement_age of 3 day(s)"));
+
+    // Overriding the As of Date with today's date lets the run through
+    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--trade-date",
+            "2025-05-26",
+            "--input",
+            input_file.to_str().unwrap(),
+            "--as-of",
+            &today,
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute program");
+
+    assert!(
+        output.status.success(),
+        "Program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let orders = fs::read_to_string(temp_path.join("test-stale-orders.csv")).unwrap();
+    assert!(orders.contains(&format!("As of Date,{} 00:00:00", today)));
+}
+
+#[test]
+fn test_multi_currency_orders() {
+    let temp_dir = TempDir::new().unwrap();
+    let temp_path = temp_dir.path();
+
+    // VTI is held in USD, worth 4000 * 1.25 = 5000 CAD, and the statement
+    // reports its totals in CAD
+    let csv_content = r#"As of Date,2025-05-24 14:39:25
+Account,BANK NAME - ACCOUNT NUMBER
+Cash,1000.00
+Investments,9000.00
+Total Value,10000.00
+,
+Symbol,Market,Description,Market Value,Currency
+VTI,,US Total Market,4000.00,USD
+XIC,,Canadian Market,4000.00,CAD
+"#;
+
+    let input_file = temp_path.join("test-fx.csv");
+    fs::write(&input_file, csv_content).unwrap();
+    fs::write(temp_path.join("fx.csv"), "Currency,Rate\nUSD,1.25\n").unwrap();
+    fs::write(
+        temp_path.join("config.toml"),
+        r#"[funds]
+VTI = "50.0"
+XIC = "50.0"
+
+[currency]
+base_currency = "CAD"
+fx_rates = "fx.csv"
+"#,
+    )
+    .unwrap();
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--trade-date",
+            "2025-05-26",
+            "--input",
+            input_file.to_str().unwrap(),
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute program");
+
+    assert!(
+        output.status.success(),
+        "Program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Total: 1000 + 5000 + 4000 = 10000 CAD
+    // Target VTI: 5000 CAD (current: 5000) -> no change
+    // Target XIC: 5000 CAD (current: 4000) -> BUY 1000 CAD
+    let output_content = fs::read_to_string(temp_path.join("test-fx-orders.csv")).unwrap();
+    let lines = order_table(&output_content);
+    assert_eq!(
+        lines,
+        vec![
+            "Symbol,Action,Amount,Currency,Quantity,Phase,Trade Date,Settlement Date",
+            "XIC,BUY,1000.00,CAD,,1,2025-05-26,2025-05-28"
+        ]
+    );
+
+    // With USD at 1.00, VTI is underweight: BUY 500 CAD = 500 USD
+    fs::write(temp_path.join("fx2.csv"), "Currency,Rate\nUSD,1.00\n").unwrap();
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--trade-date",
+            "2025-05-26",
+            "--input",
+            input_file.to_str().unwrap(),
+            "--fx-rates",
+            temp_path.join("fx2.csv").to_str().unwrap(),
+            "--skip-reconciliation",
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute program");
+
+    assert!(
+        output.status.success(),
+        "Program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output_content = fs::read_to_string(temp_path.join("test-fx-orders.csv")).unwrap();
+    assert!(output_content.contains("VTI,BUY,500.00,USD"));
+}
+
+#[test]
+fn test_ofx_statement() {
+    let temp_dir = TempDir::new().unwrap();
+    let output_file = temp_dir.path().join("orders.csv");
+
+    // The QFX example holds the same positions as examples/test-portfolio.csv
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--trade-date",
+            "2025-05-26",
+            "--input",
+            "examples/test-portfolio.qfx",
+            "--output",
+            output_file.to_str().unwrap(),
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute program");
+
+    assert!(
+        output.status.success(),
+        "Program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Parsed statement as ofx"));
+
+    let output_content = fs::read_to_string(&output_file).unwrap();
+    assert!(output_content.starts_with(
+        "Statement,test-portfolio.qfx\nAs of Date,2025-05-24 14:39:25\nAccount,EXAMPLE BANK - 123456789\n"
+    ));
+    let lines = order_table(&output_content);
+    assert_eq!(
+        lines[1..],
+        [
+            "VBTLX,SELL,5467.00,CAD,130.9461,1,2025-05-26,2025-05-28",
+            "VTSAX,BUY,5012.15,CAD,100.243,2,2025-05-28,2025-05-30",
+            "VTIAX,BUY,2954.85,CAD,73.6503,2,2025-05-28,2025-05-30",
+        ]
+    );
+}
+
+#[test]
+fn test_spreadsheet_statement() {
+    let temp_dir = TempDir::new().unwrap();
+    let output_file = temp_dir.path().join("orders.csv");
+
+    // The workbook holds the same statement as examples/test-portfolio.csv
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--trade-date",
+            "2025-05-26",
+            "--input",
+            "examples/test-portfolio.xlsx",
+            "--output",
+            output_file.to_str().unwrap(),
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute program");
+
+    assert!(
+        output.status.success(),
+        "Program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Parsed statement as spreadsheet"));
+
+    let output_content = fs::read_to_string(&output_file).unwrap();
+    let lines = order_table(&output_content);
+    assert_eq!(
+        lines[1..],
+        [
+            "VBTLX,SELL,5467.00,CAD,130.9461,1,2025-05-26,2025-05-28",
+            "VTSAX,BUY,5012.15,CAD,100.243,2,2025-05-28,2025-05-30",
+            "VTIAX,BUY,2954.85,CAD,73.6503,2,2025-05-28,2025-05-30",
+        ]
+    );
+}
+
+#[test]
+fn test_json_statement_from_stdin() {
+    use std::io::Write;
+    use std::process::Stdio;
+
+    let temp_dir = TempDir::new().unwrap();
+    let output_file = temp_dir.path().join("orders.csv");
+    let json = fs::read("examples/test-portfolio.json").unwrap();
+
+    let run = |extra_args: &[&str]| {
+        let mut child = Command::new("cargo")
+            .args([
+                "run",
+                "--",
+                "--trade-date",
+                "2025-05-26",
+                "--input",
+                "-",
+                "--config",
+                "examples/config.toml",
+            ])
+            .args(extra_args)
+            .current_dir(".")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .expect("Failed to execute program");
+        child.stdin.take().unwrap().write_all(&json).unwrap();
+        child.wait_with_output().unwrap()
+    };
+
+    // Without a file name there is no default output path
+    let output = run(&[]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--output"));
+
+    let output = run(&["--output", output_file.to_str().unwrap()]);
+    assert!(
+        output.status.success(),
+        "Program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Parsed statement as json"));
+
+    let output_content = fs::read_to_string(&output_file).unwrap();
+    assert!(output_content.starts_with("Statement,stdin\nAs of Date,2025-05-24 14:39:25\n"));
+    let lines = order_table(&output_content);
+    assert_eq!(
+        lines[1..],
+        [
+            "VBTLX,SELL,5467.00,CAD,130.9461,1,2025-05-26,2025-05-28",
+            "VTSAX,BUY,5012.15,CAD,100.243,2,2025-05-28,2025-05-30",
+            "VTIAX,BUY,2954.85,CAD,73.6503,2,2025-05-28,2025-05-30",
+        ]
+    );
+}
+
+#[test]
+fn test_household_accounts() {
+    let temp_dir = TempDir::new().unwrap();
+    let temp_path = temp_dir.path();
+
+    let rrsp = "Symbol,Market Value\nCASH,0.00\nEQUITY,6000.00\n";
+    let tfsa = "Symbol,Market Value\nCASH,1000.00\nBONDS,1000.00\n";
+    let taxable = "Symbol,Market Value\nCASH,2000.00\n";
+    fs::write(temp_path.join("rrsp.csv"), rrsp).unwrap();
+    fs::write(temp_path.join("tfsa.csv"), tfsa).unwrap();
+    fs::write(temp_path.join("taxable.csv"), taxable).unwrap();
+    fs::write(
+        temp_path.join("config.toml"),
+        "[funds]\nEQUITY = \"60.0\"\nBONDS = \"40.0\"\n",
+    )
+    .unwrap();
+
+    let input =
+        |tag: &str, file: &str| format!("{}={}", tag, temp_path.join(file).to_str().unwrap());
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--trade-date",
+            "2025-05-26",
+            "--input",
+            &input("rrsp:rrsp", "rrsp.csv"),
+            "--input",
+            &input("tfsa:tfsa", "tfsa.csv"),
+            "--input",
+            &input("taxable:taxable", "taxable.csv"),
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute program");
+
+    assert!(
+        output.status.success(),
+        "Program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // Household: 10000. BONDS 1000 -> 4000, paid for by the tfsa's and the
+    // taxable account's own cash
+    let output_content = fs::read_to_string(temp_path.join("household-orders.csv")).unwrap();
+    assert!(
+        output_content.starts_with("Statement,rrsp.csv\nAccount ID,rrsp\nAccount Type,rrsp\n,\n")
+    );
+    let lines = order_table(&output_content);
+    assert_eq!(
+        lines,
+        vec![
+            "Symbol,Action,Amount,Currency,Quantity,Phase,Trade Date,Settlement Date,Account",
+            "BONDS,BUY,1000.00,CAD,,1,2025-05-26,2025-05-28,tfsa",
+            "BONDS,BUY,2000.00,CAD,,1,2025-05-26,2025-05-28,taxable",
+        ]
+    );
+
+    // Asset location: bonds belong in the rrsp, which only has room for 6000
+    fs::write(
+        temp_path.join("config.toml"),
+        "[funds]\nEQUITY = \"60.0\"\nBONDS = \"40.0\"\n\n\
+         [[asset_location]]\nclass = \"bonds\"\nfunds = [\"BONDS\"]\naccounts = [\"rrsp\"]\n",
+    )
+    .unwrap();
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--trade-date",
+            "2025-05-26",
+            "--input",
+            &input("rrsp:rrsp", "rrsp.csv"),
+            "--input",
+            &input("tfsa:tfsa", "tfsa.csv"),
+            "--input",
+            &input("taxable:taxable", "taxable.csv"),
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute program");
+    assert!(
+        output.status.success(),
+        "Program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    let output_content = fs::read_to_string(temp_path.join("household-orders.csv")).unwrap();
+    let lines = order_table(&output_content);
+    assert_eq!(
+        lines[0],
+        "Symbol,Action,Amount,Currency,Quantity,Phase,Trade Date,Settlement Date,Account"
+    );
+    assert_eq!(
+        lines[1..],
+        [
+            "EQUITY,SELL,4000.00,CAD,,1,2025-05-26,2025-05-28,rrsp",
+            "BONDS,BUY,4000.00,CAD,,2,2025-05-28,2025-05-30,rrsp",
+            "BONDS,SELL,1000.00,CAD,,1,2025-05-26,2025-05-28,tfsa",
+            "EQUITY,BUY,2000.00,CAD,,2,2025-05-28,2025-05-30,tfsa",
+            "EQUITY,BUY,2000.00,CAD,,1,2025-05-26,2025-05-28,taxable",
+        ]
+    );
+
+    // The same account twice is rejected
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--trade-date",
+            "2025-05-26",
+            "--input",
+            &input("rrsp", "rrsp.csv"),
+            "--input",
+            &input("rrsp", "tfsa.csv"),
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute program");
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Account 'rrsp' is given more than once")
+    );
+}
+
+#[test]
+fn test_asset_class_targets() {
+    let temp_dir = TempDir::new().unwrap();
+    let temp_path = temp_dir.path();
+
+    let csv_content =
+        "Symbol,Market Value\nCASH,1000.00\nVTSAX,3000.00\nVTI,2000.00\nVBTLX,4000.00\n";
+    let config_content = r#"
+[asset_classes.equity]
+target = 60
+
+[asset_classes.equity.us]
+target = 40
+funds = ["VTSAX", "VTI"]
+
+[asset_classes.equity.international]
+target = 20
+funds = ["VTIAX"]
+
+[asset_classes.bonds]
+target = 40
+funds = ["VBTLX"]
+"#;
+    fs::write(temp_path.join("portfolio.csv"), csv_content).unwrap();
+    fs::write(temp_path.join("config.toml"), config_content).unwrap();
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--trade-date",
+            "2025-05-26",
+            "--input",
+            temp_path.join("portfolio.csv").to_str().unwrap(),
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute program");
+
+    assert!(
+        output.status.success(),
+        "Program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout).contains("Loaded config with 4 funds"));
+
+    // Household: 10000. US equity 5000 -> 4000, sold from the secondary fund;
+    // international 0 -> 2000, bought in its only fund; bonds on target
+    let output_content = fs::read_to_string(temp_path.join("portfolio-orders.csv")).unwrap();
+    let lines = order_table(&output_content);
+    assert_eq!(
+        lines[1..],
+        [
+            "VTI,SELL,1000.00,CAD,,1,2025-05-26,2025-05-28",
+            "VTIAX,BUY,2000.00,CAD,,2,2025-05-28,2025-05-30",
+        ]
+    );
+
+    // Subclasses that do not add up to their parent are rejected
+    fs::write(
+        temp_path.join("config.toml"),
+        config_content.replacen("target = 60", "target = 70", 1),
+    )
+    .unwrap();
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--trade-date",
+            "2025-05-26",
+            "--input",
+            temp_path.join("portfolio.csv").to_str().unwrap(),
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute program");
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("asset_classes.equity.target: target is 70% but its subclasses add up to 60%"));
+}
+
+#[test]
+fn test_buy_only_mode() {
+    let temp_dir = TempDir::new().unwrap();
+    let output_file = temp_dir.path().join("orders.csv");
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--trade-date",
+            "2025-05-26",
+            "--input",
+            "examples/test-portfolio.csv",
+            "--output",
+            output_file.to_str().unwrap(),
+            "--mode",
+            "buy-only",
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute program");
+
+    assert!(
+        output.status.success(),
+        "Program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // The 2500 of cash is split so that VTSAX (5012.15 under target) and
+    // VTIAX (2954.85 under) end up equally far from their targets; the
+    // overweight VBTLX is not sold
+    let output_content = fs::read_to_string(&output_file).unwrap();
+    let lines = order_table(&output_content);
+    assert_eq!(
+        lines[1..],
+        [
+            "VTSAX,BUY,2278.65,CAD,45.573,1,2025-05-26,2025-05-28",
+            "VTIAX,BUY,221.35,CAD,5.5172,1,2025-05-26,2025-05-28",
+        ]
+    );
+}
+
+#[test]
+fn test_share_rounding() {
+    let temp_dir = TempDir::new().unwrap();
+    let output_file = temp_dir.path().join("orders.csv");
+    let config_file = temp_dir.path().join("config.toml");
+    let config_content = r#"[funds]
+VTSAX = 60
+VTIAX = 30
+VBTLX = 10
+
+[shares]
+VTSAX = "whole"
+VTIAX = "fractional:2"
+"#;
+    fs::write(&config_file, config_content).unwrap();
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--trade-date",
+            "2025-05-26",
+            "--input",
+            "examples/test-portfolio.csv",
+            "--config",
+            config_file.to_str().unwrap(),
+            "--output",
+            output_file.to_str().unwrap(),
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute program");
+
+    assert!(
+        output.status.success(),
+        "Program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    // VTSAX rounds down to 100 shares at $50, leaving 12.15 of its buy
+    // unspent; that is not enough for another share, but buys one more
+    // hundredth of a VTIAX share. VBTLX is ordered in dollars
+    let output_content = fs::read_to_string(&output_file).unwrap();
+    let lines = order_table(&output_content);
+    assert_eq!(
+        lines[1..],
+        [
+            "VBTLX,SELL,5467.00,CAD,130.9461,1,2025-05-26,2025-05-28",
+            "VTSAX,BUY,5000.00,CAD,100,2,2025-05-28,2025-05-30",
+            "VTIAX,BUY,2955.24,CAD,73.66,2,2025-05-28,2025-05-30",
+        ]
+    );
+}
+
+#[test]
+fn test_unmanaged_holdings() {
+    let temp_dir = TempDir::new().unwrap();
+    let output_file = temp_dir.path().join("orders.csv");
+    let config_file = temp_dir.path().join("config.toml");
+    let config_content = "[funds]\nVTSAX = 70\nVTIAX = 30\n";
+
+    let run = || {
+        Command::new("cargo")
+            .args([
+                "run",
+                "--",
+                "--trade-date",
+                "2025-05-26",
+                "--input",
+                "examples/test-portfolio.csv",
+                "--config",
+                config_file.to_str().unwrap(),
+                "--output",
+                output_file.to_str().unwrap(),
+            ])
+            .current_dir(".")
+            .output()
+            .expect("Failed to execute program")
+    };
+
+    // VBTLX has no target, so by default it is left out with a warning
+    fs::write(&config_file, config_content).unwrap();
+    let output = run();
+    assert!(
+        output.status.success(),
+        "Program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "Warning: VBTLX ($10470.05 in account test-portfolio) has no target in the config; \
+         leaving it out of the portfolio"
+    ));
+
+    // With the error policy, no orders are generated
+    fs::write(
+        &config_file,
+        format!(
+            "{}[targets]\nunmanaged_holdings = \"error\"\n",
+            config_content
+        ),
+    )
+    .unwrap();
+    let output = run();
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr)
+        .contains("Holdings not in the config: VBTLX ($10470.05 in account test-portfolio)"));
+
+    // Liquidated, its proceeds and the cash are split 70/30
+    fs::write(
+        &config_file,
+        format!(
+            "{}[targets]\nunmanaged_holdings = \"liquidate\"\n",
+            config_content
+        ),
+    )
+    .unwrap();
+    let output = run();
+    assert!(
+        output.status.success(),
+        "Program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "Warning: VBTLX ($10470.05 in account test-portfolio) has no target in the config; \
+         selling all of it"
+    ));
+    let output_content = fs::read_to_string(&output_file).unwrap();
+    let lines = order_table(&output_content);
+    assert_eq!(
+        lines[1..],
+        [
+            "VBTLX,SELL,10470.05,CAD,250.789,1,2025-05-26,2025-05-28",
+            "VTSAX,BUY,10015.20,CAD,200.304,2,2025-05-28,2025-05-30",
+            "VTIAX,BUY,2954.85,CAD,73.6503,2,2025-05-28,2025-05-30",
+        ]
+    );
+}
+
+#[test]
+fn test_settlement_phases() {
+    let temp_dir = TempDir::new().unwrap();
+    let output_file = temp_dir.path().join("orders.csv");
+    let config_file = temp_dir.path().join("config.toml");
+    let config_content = r#"[funds]
+VTSAX = 60
+VTIAX = 30
+VBTLX = 10
+
+[settlement]
+days = 1
+
+[settlement.funds]
+VBTLX = 3
+"#;
+    fs::write(&config_file, config_content).unwrap();
+
+    let output = Command::new("cargo")
+        .args([
+            "run",
+            "--",
+            "--trade-date",
+            "2025-05-30",
+            "--input",
+            "examples/test-portfolio.csv",
+            "--config",
+            config_file.to_str().unwrap(),
+            "--output",
+            output_file.to_str().unwrap(),
+        ])
+        .current_dir(".")
+        .output()
+        .expect("Failed to execute program");
+
+    assert!(
+        output.status.success(),
+        "Program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("Phase 2: 2 buy(s) wait for sales to settle, until 2025-06-04"));
+
+    // The 2500 of cash covers neither buy, so both wait for the VBTLX sale
+    // placed on Friday to settle three business days later
+    let output_content = fs::read_to_string(&output_file).unwrap();
+    let lines = order_table(&output_content);
+    assert_eq!(
+        lines[1..],
+        [
+            "VBTLX,SELL,5467.00,CAD,130.9461,1,2025-05-30,2025-06-04",
+            "VTSAX,BUY,5012.15,CAD,100.243,2,2025-06-04,2025-06-05",
+            "VTIAX,BUY,2954.85,CAD,73.6503,2,2025-06-04,2025-06-05",
+        ]
+    );
+}
+
+#[test]
+fn test_contribute_and_withdraw() {
+    let temp_dir = TempDir::new().unwrap();
+    let output_file = temp_dir.path().join("orders.csv");
+
+    let run = |extra_args: &[&str]| {
+        Command::new("cargo")
+            .args([
+                "run",
+                "--",
+                "--trade-date",
+                "2025-05-26",
+                "--input",
+                "examples/test-portfolio.csv",
+                "--output",
+                output_file.to_str().unwrap(),
+            ])
+            .args(extra_args)
+            .current_dir(".")
+            .output()
+            .expect("Failed to execute program")
+    };
+
+    // The statement still reconciles, since the cash flow is applied after
+    let output = run(&["--withdraw", "12000"]);
+    assert!(
+        output.status.success(),
+        "Program failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert!(String::from_utf8_lossy(&output.stdout)
+        .contains("Withdrawal: $12000.00 CAD (account test-portfolio)"));
+    let output_content = fs::read_to_string(&output_file).unwrap();
+    assert!(output_content.contains(",\nWithdrawal,12000.00,CAD\n,\n"));
+    let lines = order_table(&output_content);
+    assert_eq!(lines.len(), 4);
+    let sold: f64 = lines[1..]
+        .iter()
+        .map(|line| {
+            assert!(line.contains(",SELL,"), "{}", line);
+            line.split(',').nth(2).unwrap().parse::<f64>().unwrap()
+        })
+        .sum();
+    // The statement's 2500 of cash covers the rest
+    assert!((sold - 9500.0).abs() < 0.001, "sold {}", sold);
+
+    let output = run(&["--contribute", "5000", "--mode", "buy-only"]);
+    assert!(output.status.success());
+    let output_content = fs::read_to_string(&output_file).unwrap();
+    let lines = order_table(&output_content);
+    assert_eq!(
+        lines[1..],
+        [
+            "VTSAX,BUY,5528.65,CAD,110.573,1,2025-05-26,2025-05-28",
+            "VTIAX,BUY,1971.35,CAD,49.1363,1,2025-05-26,2025-05-28",
+        ]
+    );
+
+    let output = run(&["--withdraw", "rrsp=100"]);
+    assert!(!output.status.success());
+    assert!(
+        String::from_utf8_lossy(&output.stderr).contains("Withdrawal for unknown account 'rrsp'")
+    );
+
+    // The portfolio is worth 50030.50 in all
+    let output = run(&["--withdraw", "100000"]);
+    assert!(!output.status.success());
+    assert!(String::from_utf8_lossy(&output.stderr).contains(
+        "The withdrawal from account test-portfolio is $49969.50 more than its cash and holdings"
+    ));
+}